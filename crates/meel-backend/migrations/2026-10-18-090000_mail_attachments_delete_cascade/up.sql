@@ -0,0 +1,4 @@
+ALTER TABLE mail_attachments
+DROP CONSTRAINT mail_attachments_mail_id_fkey,
+ADD CONSTRAINT mail_attachments_mail_id_fkey
+    FOREIGN KEY (mail_id) REFERENCES mails(id) ON DELETE CASCADE;
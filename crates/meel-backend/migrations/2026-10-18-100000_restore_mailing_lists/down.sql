@@ -0,0 +1,2 @@
+DROP TABLE mailing_list_subscribers;
+DROP TABLE mailing_lists;
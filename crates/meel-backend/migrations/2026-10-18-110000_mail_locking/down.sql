@@ -0,0 +1,3 @@
+DROP INDEX mails_unsent_scheduled_at_idx;
+
+ALTER TABLE mails DROP COLUMN locked_until;
@@ -0,0 +1,3 @@
+ALTER TABLE mails ADD COLUMN locked_until TIMESTAMP;
+
+CREATE INDEX mails_unsent_scheduled_at_idx ON mails (scheduled_at) WHERE sent_at IS NULL;
@@ -0,0 +1 @@
+ALTER TABLE mails DROP COLUMN next_attempt_at;
@@ -0,0 +1 @@
+ALTER TABLE mails ADD COLUMN next_attempt_at TIMESTAMP;
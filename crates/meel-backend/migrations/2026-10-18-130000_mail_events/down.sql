@@ -0,0 +1,3 @@
+ALTER TABLE mails DROP COLUMN last_error;
+
+DROP TABLE mail_events;
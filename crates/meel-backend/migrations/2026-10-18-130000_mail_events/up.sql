@@ -0,0 +1,15 @@
+CREATE TABLE mail_events
+(
+    id         SERIAL PRIMARY KEY,
+    mail_id    INTEGER   NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    event_type TEXT      NOT NULL,
+    smtp_code  INTEGER,
+    message    TEXT,
+
+    FOREIGN KEY (mail_id) REFERENCES mails (id) ON DELETE CASCADE
+);
+
+CREATE INDEX mail_events_mail_id_idx ON mail_events (mail_id);
+
+ALTER TABLE mails ADD COLUMN last_error TEXT;
@@ -0,0 +1,3 @@
+DROP INDEX mails_status_idx;
+
+ALTER TABLE mails DROP COLUMN status;
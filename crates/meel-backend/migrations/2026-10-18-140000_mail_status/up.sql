@@ -0,0 +1,5 @@
+ALTER TABLE mails ADD COLUMN status TEXT NOT NULL DEFAULT 'queued';
+
+UPDATE mails SET status = 'sent' WHERE sent_at IS NOT NULL;
+
+CREATE INDEX mails_status_idx ON mails (status);
@@ -0,0 +1,3 @@
+ALTER TABLE mails DROP CONSTRAINT mails_status_check;
+
+UPDATE mails SET status = 'queued' WHERE status IN ('sending', 'retrying');
@@ -0,0 +1,6 @@
+-- Mails that used up their attempts are failed by the scheduler, which knows MEEL_MAX_SEND_ATTEMPTS.
+UPDATE mails SET status = 'retrying' WHERE status = 'queued' AND send_attempts > 0;
+
+ALTER TABLE mails
+ADD CONSTRAINT mails_status_check
+    CHECK (status IN ('queued', 'sending', 'sent', 'retrying', 'failed', 'cancelled'));
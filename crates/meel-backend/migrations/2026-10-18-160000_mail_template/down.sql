@@ -0,0 +1,4 @@
+DROP INDEX mails_scheduled_at_idx;
+DROP INDEX mails_template_idx;
+
+ALTER TABLE mails DROP COLUMN template;
@@ -0,0 +1,4 @@
+ALTER TABLE mails ADD COLUMN template TEXT;
+
+CREATE INDEX mails_template_idx ON mails (template);
+CREATE INDEX mails_scheduled_at_idx ON mails (scheduled_at);
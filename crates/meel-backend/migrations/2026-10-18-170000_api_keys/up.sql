@@ -0,0 +1,11 @@
+CREATE TABLE api_keys
+(
+    id           SERIAL PRIMARY KEY,
+    created_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    name         TEXT      NOT NULL,
+    key_prefix   TEXT      NOT NULL,
+    key_hash     TEXT      NOT NULL UNIQUE,
+    scopes       TEXT[]    NOT NULL,
+    last_used_at TIMESTAMP,
+    revoked_at   TIMESTAMP
+);
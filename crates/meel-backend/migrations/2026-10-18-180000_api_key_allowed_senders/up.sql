@@ -0,0 +1 @@
+ALTER TABLE api_keys ADD COLUMN allowed_senders TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,29 @@
+CREATE TABLE mail_recipients
+(
+    id         SERIAL PRIMARY KEY,
+    mail_id    INTEGER   NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    kind       TEXT      NOT NULL CHECK (kind IN ('to', 'cc', 'bcc')),
+    address    TEXT      NOT NULL,
+    status     TEXT      NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'sent', 'failed')),
+    smtp_code  INTEGER,
+    last_error TEXT,
+
+    FOREIGN KEY (mail_id) REFERENCES mails (id) ON DELETE CASCADE
+);
+
+CREATE INDEX mail_recipients_mail_id_idx ON mail_recipients (mail_id);
+CREATE INDEX mail_recipients_address_idx ON mail_recipients (address);
+
+-- Every existing mail has exactly one recipient.
+INSERT INTO mail_recipients (mail_id, kind, address, status, last_error)
+SELECT id,
+       'to',
+       recipient,
+       CASE
+           WHEN sent_at IS NOT NULL THEN 'sent'
+           WHEN status IN ('failed', 'cancelled') THEN 'failed'
+           ELSE 'pending'
+           END,
+       last_error
+FROM mails;
@@ -0,0 +1,6 @@
+DROP INDEX mails_metadata_idx;
+DROP INDEX mails_tags_idx;
+
+ALTER TABLE mails DROP COLUMN metadata;
+ALTER TABLE mails DROP COLUMN tags;
+ALTER TABLE mails DROP COLUMN headers;
@@ -0,0 +1,6 @@
+ALTER TABLE mails ADD COLUMN headers JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE mails ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE mails ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
+
+CREATE INDEX mails_tags_idx ON mails USING GIN (tags);
+CREATE INDEX mails_metadata_idx ON mails USING GIN (metadata jsonb_path_ops);
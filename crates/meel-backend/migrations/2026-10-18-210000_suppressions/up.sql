@@ -0,0 +1,11 @@
+CREATE TABLE suppressions
+(
+    id              SERIAL PRIMARY KEY,
+    created_at      TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    email           TEXT      NOT NULL UNIQUE,
+    reason          TEXT      NOT NULL,
+    -- The list the address unsubscribed from, if any. Suppressions apply to every mail regardless.
+    mailing_list_id INTEGER,
+
+    FOREIGN KEY (mailing_list_id) REFERENCES mailing_lists (id) ON DELETE SET NULL
+);
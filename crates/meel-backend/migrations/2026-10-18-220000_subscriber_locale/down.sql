@@ -0,0 +1 @@
+ALTER TABLE mailing_list_subscribers DROP COLUMN locale;
@@ -0,0 +1,5 @@
+DROP INDEX mail_recipients_normalized_address_idx;
+CREATE INDEX mail_recipients_address_idx ON mail_recipients (address);
+ALTER TABLE mail_recipients DROP COLUMN normalized_address;
+
+ALTER TABLE mails ALTER COLUMN created_at DROP NOT NULL;
@@ -0,0 +1,13 @@
+UPDATE mails
+SET created_at = COALESCE(updated_at, scheduled_at, CURRENT_TIMESTAMP)
+WHERE created_at IS NULL;
+ALTER TABLE mails ALTER COLUMN created_at SET NOT NULL;
+
+-- The bare, lowercased email address, so the recipient filter can use an index.
+ALTER TABLE mail_recipients ADD COLUMN normalized_address TEXT;
+UPDATE mail_recipients
+SET normalized_address = lower(trim(COALESCE(substring(address FROM '<([^<>]*)>\s*$'), address)));
+ALTER TABLE mail_recipients ALTER COLUMN normalized_address SET NOT NULL;
+
+DROP INDEX mail_recipients_address_idx;
+CREATE INDEX mail_recipients_normalized_address_idx ON mail_recipients (normalized_address);
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use axum::http::StatusCode;
+use lettre::message::Mailbox;
+use meel_utils::api_error::{ApiError, ApiErrorCode};
+
+use crate::auth::AuthenticatedKey;
+
+/// Parse a comma separated list of allowed senders, as used by `MEEL_ALLOWED_SENDERS`.
+fn parse_allowed_senders(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+/// The senders every API key is restricted to, an empty list allows any sender.
+fn get_allowed_senders() -> Vec<String> {
+    meel_utils::env::get_var("MEEL_ALLOWED_SENDERS", None)
+        .map(|value| parse_allowed_senders(&value))
+        .unwrap_or_default()
+}
+
+/// Check an address against a list of allowed senders. Entries containing an `@` match a single
+/// address, other entries (optionally prefixed with `@`) match every address on that domain.
+fn matches_allowed_senders(allowed_senders: &[String], address: &str) -> bool {
+    let address = address.to_lowercase();
+    let Some((_, domain)) = address.rsplit_once('@') else {
+        return false;
+    };
+
+    allowed_senders.iter().any(|entry| {
+        let entry = entry.to_lowercase();
+
+        match entry.strip_prefix('@') {
+            Some(allowed_domain) => allowed_domain == domain,
+            None if entry.contains('@') => entry == address,
+            None => entry == domain,
+        }
+    })
+}
+
+fn sender_not_allowed(sender: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::FORBIDDEN,
+        ApiErrorCode::SenderNotAllowed,
+        format!("Sending as `{sender}` is not allowed"),
+        HashMap::new(),
+    )
+}
+
+/// Make sure the sender is allowed both by the server configuration and by the API key.
+pub fn check_sender(api_key: &AuthenticatedKey, sender: &str) -> Result<(), ApiError> {
+    // A sender that can't be parsed can't match any of the allowed senders.
+    let address = sender
+        .parse::<Mailbox>()
+        .map(|mailbox| mailbox.email.to_string())
+        .unwrap_or_default();
+
+    let allowed_senders = get_allowed_senders();
+    if !allowed_senders.is_empty() && !matches_allowed_senders(&allowed_senders, &address) {
+        return Err(sender_not_allowed(sender));
+    }
+
+    if !api_key.allowed_senders.is_empty()
+        && !matches_allowed_senders(&api_key.allowed_senders, &address)
+    {
+        return Err(sender_not_allowed(sender));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_matches_allowed_senders() {
+    let allowed_senders = parse_allowed_senders("noreply@example.com, @example.org,example.net");
+
+    assert!(matches_allowed_senders(
+        &allowed_senders,
+        "noreply@example.com"
+    ));
+    assert!(matches_allowed_senders(
+        &allowed_senders,
+        "NoReply@Example.com"
+    ));
+    assert!(!matches_allowed_senders(
+        &allowed_senders,
+        "ceo@example.com"
+    ));
+    assert!(matches_allowed_senders(
+        &allowed_senders,
+        "news@example.org"
+    ));
+    assert!(matches_allowed_senders(
+        &allowed_senders,
+        "news@example.net"
+    ));
+    assert!(!matches_allowed_senders(
+        &allowed_senders,
+        "news@sub.example.net"
+    ));
+    assert!(!matches_allowed_senders(&allowed_senders, "not an address"));
+}
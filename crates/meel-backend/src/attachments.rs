@@ -0,0 +1,151 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn get_attachment_directory() -> String {
+    format!(
+        "{}/attachments",
+        meel_utils::env::get_var("MEEL_DATA_DIRECTORY", Some("./data")).unwrap()
+    )
+}
+
+fn get_mail_attachment_directory(mail_id: i32) -> PathBuf {
+    Path::new(&get_attachment_directory()).join(mail_id.to_string())
+}
+
+fn get_shared_attachment_directory(shared_id: &str) -> PathBuf {
+    Path::new(&get_attachment_directory())
+        .join("shared")
+        .join(shared_id)
+}
+
+/// Strip any directory components from a user supplied file name, so it can't escape the
+/// attachment directory.
+pub fn sanitize_file_name(file_name: &str) -> Option<String> {
+    let file_name = Path::new(file_name.trim())
+        .file_name()?
+        .to_str()?
+        .to_string();
+
+    if file_name.is_empty() || file_name.starts_with('.') {
+        return None;
+    }
+
+    Some(file_name)
+}
+
+/// Write the attachment contents to disk, returning the path it was stored at.
+pub fn store_attachment(
+    mail_id: i32,
+    index: usize,
+    file_name: &str,
+    contents: &[u8],
+) -> Result<String, String> {
+    let directory = get_mail_attachment_directory(mail_id);
+
+    if fs::create_dir_all(&directory).is_err() {
+        return Err("Failed to create attachment directory".to_string());
+    }
+
+    write_attachment(&directory, index, file_name, contents)
+}
+
+/// Create a directory for attachments that several mails share, such as those of a mailing list
+/// send, returning its id.
+pub fn create_shared_directory() -> Result<String, String> {
+    let shared_id = format!("{:016x}", fastrand::u64(..));
+    let directory = get_shared_attachment_directory(&shared_id);
+
+    // Only the last directory is created with `create_dir`, which fails when it already exists, so
+    // two sends never end up sharing a directory.
+    let created = match directory.parent() {
+        Some(parent) => fs::create_dir_all(parent).and_then(|_| fs::create_dir(&directory)),
+        None => fs::create_dir(&directory),
+    };
+
+    match created {
+        Ok(_) => Ok(shared_id),
+        Err(_) => Err("Failed to create attachment directory".to_string()),
+    }
+}
+
+/// Write the contents of a shared attachment to disk, returning the path it was stored at.
+pub fn store_shared_attachment(
+    shared_id: &str,
+    index: usize,
+    file_name: &str,
+    contents: &[u8],
+) -> Result<String, String> {
+    write_attachment(
+        &get_shared_attachment_directory(shared_id),
+        index,
+        file_name,
+        contents,
+    )
+}
+
+fn write_attachment(
+    directory: &Path,
+    index: usize,
+    file_name: &str,
+    contents: &[u8],
+) -> Result<String, String> {
+    // Prefix the index, so two attachments with the same name don't overwrite each other.
+    let path = directory.join(format!("{index}-{file_name}"));
+
+    match fs::write(&path, contents) {
+        Ok(_) => Ok(path.display().to_string()),
+        Err(_) => Err(format!("Failed to write attachment {file_name}")),
+    }
+}
+
+pub fn read_attachment(file_path: &str) -> Result<Vec<u8>, String> {
+    fs::read(file_path).map_err(|_| format!("Failed to read attachment {file_path}"))
+}
+
+/// Remove all stored attachments of a mail.
+pub fn remove_attachments(mail_id: i32) {
+    let directory = get_mail_attachment_directory(mail_id);
+
+    if directory.exists() && fs::remove_dir_all(&directory).is_err() {
+        tracing::error!("Failed to remove attachments of mail {}", mail_id);
+    }
+}
+
+/// Remove a directory of shared attachments, for when the mails sharing them weren't saved.
+pub fn remove_shared_attachments(shared_id: &str) {
+    let directory = get_shared_attachment_directory(shared_id);
+
+    if directory.exists() && fs::remove_dir_all(&directory).is_err() {
+        tracing::error!("Failed to remove shared attachments {}", shared_id);
+    }
+}
+
+/// Remove an attachment file that no mail uses anymore, and its directory once that is empty.
+pub fn remove_attachment_file(file_path: &str) {
+    let path = Path::new(file_path);
+
+    if path.exists() && fs::remove_file(path).is_err() {
+        tracing::error!("Failed to remove attachment {}", file_path);
+        return;
+    }
+
+    if let Some(directory) = path.parent() {
+        // Fails while other attachments are left in the directory, which is fine.
+        let _ = fs::remove_dir(directory);
+    }
+}
+
+#[test]
+fn test_sanitize_file_name() {
+    assert_eq!(
+        sanitize_file_name("invoice.pdf"),
+        Some("invoice.pdf".to_string())
+    );
+    assert_eq!(
+        sanitize_file_name("../../etc/passwd"),
+        Some("passwd".to_string())
+    );
+    assert_eq!(sanitize_file_name(".."), None);
+    assert_eq!(sanitize_file_name(".env"), None);
+    assert_eq!(sanitize_file_name(""), None);
+}
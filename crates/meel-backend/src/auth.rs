@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Extension;
+use base64::Engine;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl, SelectableHelper};
+use meel_utils::api_error::{ApiError, ApiErrorCode};
+use sha2::{Digest, Sha256};
+use tracing::Instrument;
+
+use crate::database;
+use crate::database::models::ApiKey;
+
+/// Prefix of every generated API key, so leaked keys are easy to recognize.
+const API_KEY_PREFIX: &str = "meel_";
+
+/// The number of characters of a key that are stored in plain text, to tell keys apart.
+const VISIBLE_KEY_LENGTH: usize = 12;
+
+/// How outdated the last use of a key may get, so not every request writes to the database.
+const LAST_USED_PRECISION: Duration = Duration::from_secs(60);
+
+/// What an API key is allowed to do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiKeyScope {
+    Send,
+    ReadMail,
+    ManageLists,
+    ManageTemplates,
+    /// Grants every other scope, and allows managing API keys.
+    Admin,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::Send => "send",
+            ApiKeyScope::ReadMail => "read-mail",
+            ApiKeyScope::ManageLists => "manage-lists",
+            ApiKeyScope::ManageTemplates => "manage-templates",
+            ApiKeyScope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for ApiKeyScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ApiKeyScope {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "send" => Ok(ApiKeyScope::Send),
+            "read-mail" => Ok(ApiKeyScope::ReadMail),
+            "manage-lists" => Ok(ApiKeyScope::ManageLists),
+            "manage-templates" => Ok(ApiKeyScope::ManageTemplates),
+            "admin" => Ok(ApiKeyScope::Admin),
+            _ => Err(format!("Unknown API key scope `{value}`")),
+        }
+    }
+}
+
+/// The API key a request was authenticated with, available to handlers as an extension.
+#[derive(Clone)]
+pub struct AuthenticatedKey {
+    /// `None` for the admin bootstrap key, which doesn't live in the database.
+    pub id: Option<i32>,
+    pub name: String,
+    pub scopes: Vec<ApiKeyScope>,
+    /// Senders this key may send as, on top of `MEEL_ALLOWED_SENDERS`. Empty allows any sender.
+    pub allowed_senders: Vec<String>,
+}
+
+impl AuthenticatedKey {
+    pub fn has_scope(&self, scope: ApiKeyScope) -> bool {
+        self.scopes
+            .iter()
+            .any(|granted| *granted == scope || *granted == ApiKeyScope::Admin)
+    }
+}
+
+pub fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Generate a new random API key, returning the key and its visible prefix.
+pub fn generate_api_key() -> Result<(String, String), String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|err| err.to_string())?;
+
+    let key = API_KEY_PREFIX.to_string()
+        + &base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+    let prefix = key[..VISIBLE_KEY_LENGTH].to_string();
+
+    Ok((key, prefix))
+}
+
+fn get_admin_api_key() -> Option<String> {
+    meel_utils::env::get_var("MEEL_ADMIN_API_KEY", None).filter(|key| !key.trim().is_empty())
+}
+
+/// Warn at startup when there is no way to create the first API key.
+pub fn check_admin_api_key() {
+    if get_admin_api_key().is_none() {
+        tracing::warn!("MEEL_ADMIN_API_KEY is not set, API keys can't be managed through the API");
+    }
+}
+
+fn unauthorized(message: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::UNAUTHORIZED,
+        ApiErrorCode::Unauthorized,
+        message.to_string(),
+        HashMap::new(),
+    )
+}
+
+fn get_bearer_token(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+fn find_api_key(
+    pool: &Extension<Arc<database::ConnectionPool>>,
+    token: &str,
+) -> Result<Option<AuthenticatedKey>, ApiError> {
+    use crate::database::schema::api_keys;
+
+    if let Some(admin_key) = get_admin_api_key() {
+        // Compare the hashes, so the comparison time doesn't depend on the key contents.
+        if hash_api_key(&admin_key) == hash_api_key(token) {
+            return Ok(Some(AuthenticatedKey {
+                id: None,
+                name: "admin".to_string(),
+                scopes: vec![ApiKeyScope::Admin],
+                allowed_senders: vec![],
+            }));
+        }
+    }
+
+    let mut conn = crate::routes::get_connection(pool)?;
+
+    let api_key = api_keys::table
+        .filter(api_keys::key_hash.eq(hash_api_key(token)))
+        .filter(api_keys::revoked_at.is_null())
+        .select(ApiKey::as_select())
+        .first(&mut conn)
+        .optional()
+        .map_err(|err| crate::routes::database_error("Failed to load API key", err))?;
+
+    let Some(api_key) = api_key else {
+        return Ok(None);
+    };
+
+    let now = SystemTime::now();
+    let recently_used = api_key.last_used_at.is_some_and(|last_used_at| {
+        now.duration_since(last_used_at)
+            .is_ok_and(|elapsed| elapsed < LAST_USED_PRECISION)
+    });
+
+    if !recently_used {
+        if let Err(err) = diesel::update(api_keys::table.find(api_key.id))
+            .set(api_keys::last_used_at.eq(now))
+            .execute(&mut conn)
+        {
+            tracing::error!(
+                "Failed to update last use of API key {}: {}",
+                api_key.id,
+                err
+            );
+        }
+    }
+
+    Ok(Some(AuthenticatedKey {
+        id: Some(api_key.id),
+        name: api_key.name,
+        // Unknown scopes can only come from a manual edit, ignore them rather than failing.
+        scopes: api_key
+            .scopes
+            .iter()
+            .filter_map(|scope| scope.parse().ok())
+            .collect(),
+        allowed_senders: api_key.allowed_senders,
+    }))
+}
+
+/// Middleware that rejects requests without a valid API key that has the given scope.
+pub async fn require_scope(
+    State(scope): State<ApiKeyScope>,
+    pool: Extension<Arc<database::ConnectionPool>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(token) = get_bearer_token(&request) else {
+        return Err(unauthorized("Missing API key"));
+    };
+
+    let Some(api_key) = find_api_key(&pool, token)? else {
+        return Err(unauthorized("Invalid API key"));
+    };
+
+    if !api_key.has_scope(scope) {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            ApiErrorCode::Forbidden,
+            format!("API key is missing the `{scope}` scope"),
+            HashMap::new(),
+        ));
+    }
+
+    let span = tracing::info_span!("api_key", id = api_key.id, name = %api_key.name);
+    request.extensions_mut().insert(api_key);
+    Ok(next.run(request).instrument(span).await)
+}
+
+#[test]
+fn test_api_key_scope_round_trip() {
+    for scope in [
+        ApiKeyScope::Send,
+        ApiKeyScope::ReadMail,
+        ApiKeyScope::ManageLists,
+        ApiKeyScope::ManageTemplates,
+        ApiKeyScope::Admin,
+    ] {
+        assert_eq!(scope.as_str().parse::<ApiKeyScope>(), Ok(scope));
+    }
+}
@@ -5,6 +5,7 @@ pub mod models;
 pub mod schema;
 
 pub type ConnectionPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+pub type PooledConnection = r2d2::PooledConnection<ConnectionManager<PgConnection>>;
 
 pub fn establish_connection_pool() -> ConnectionPool {
     let database_url =
@@ -2,7 +2,10 @@ use std::time::SystemTime;
 
 use diesel::prelude::*;
 
-use crate::database::schema::mails;
+use crate::database::schema::{
+    api_keys, mail_attachments, mail_events, mail_recipients, mailing_list_subscribers,
+    mailing_lists, mails, suppressions,
+};
 
 #[derive(Queryable, Selectable, Clone)]
 #[diesel(table_name = mails)]
@@ -10,7 +13,7 @@ use crate::database::schema::mails;
 #[allow(dead_code)]
 pub struct Mail {
     pub id: i32,
-    pub created_at: Option<SystemTime>,
+    pub created_at: SystemTime,
     pub updated_at: Option<SystemTime>,
     pub sender: String,
     pub recipient: String,
@@ -22,6 +25,14 @@ pub struct Mail {
     pub sent_at: Option<SystemTime>,
     pub scheduled_at: SystemTime,
     pub reply_to: Option<String>,
+    pub locked_until: Option<SystemTime>,
+    pub next_attempt_at: Option<SystemTime>,
+    pub last_error: Option<String>,
+    pub status: String,
+    pub template: Option<String>,
+    pub headers: serde_json::Value,
+    pub tags: Vec<String>,
+    pub metadata: serde_json::Value,
 }
 
 #[derive(Insertable)]
@@ -36,4 +47,185 @@ pub struct NewMail<'a> {
     pub priority: i32,
     pub scheduled_at: SystemTime,
     pub reply_to: Option<&'a str>,
+    pub template: Option<&'a str>,
+    pub headers: &'a serde_json::Value,
+    pub tags: &'a [String],
+    pub metadata: &'a serde_json::Value,
+}
+
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = mails)]
+pub struct UpdateMail<'a> {
+    pub recipient: Option<&'a str>,
+    pub subject: Option<&'a str>,
+    pub priority: Option<i32>,
+    pub scheduled_at: Option<SystemTime>,
+    pub next_attempt_at: Option<Option<SystemTime>>,
+    pub status: Option<&'a str>,
+}
+
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = mail_attachments)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(dead_code)]
+pub struct MailAttachment {
+    pub id: i32,
+    pub mail_id: i32,
+    pub created_at: Option<SystemTime>,
+    pub file_name: String,
+    pub file_type: String,
+    pub file_size: i32,
+    pub file_path: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = mail_attachments)]
+pub struct NewMailAttachment<'a> {
+    pub mail_id: i32,
+    pub file_name: &'a str,
+    pub file_type: &'a str,
+    pub file_size: i32,
+    pub file_path: &'a str,
+}
+
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = mail_events)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(dead_code)]
+pub struct MailEvent {
+    pub id: i32,
+    pub mail_id: i32,
+    pub created_at: SystemTime,
+    pub event_type: String,
+    pub smtp_code: Option<i32>,
+    pub message: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = mail_events)]
+pub struct NewMailEvent<'a> {
+    pub mail_id: i32,
+    /// Defaults to the current time when not set.
+    pub created_at: Option<SystemTime>,
+    pub event_type: &'a str,
+    pub smtp_code: Option<i32>,
+    pub message: Option<&'a str>,
+}
+
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = mail_recipients)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(dead_code)]
+pub struct MailRecipient {
+    pub id: i32,
+    pub mail_id: i32,
+    pub created_at: SystemTime,
+    pub kind: String,
+    pub address: String,
+    pub status: String,
+    pub smtp_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub normalized_address: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = mail_recipients)]
+pub struct NewMailRecipient<'a> {
+    pub mail_id: i32,
+    pub kind: &'a str,
+    pub address: &'a str,
+    pub normalized_address: String,
+}
+
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = mailing_lists)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MailingList {
+    pub id: i32,
+    pub created_at: Option<SystemTime>,
+    pub updated_at: Option<SystemTime>,
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = mailing_lists)]
+pub struct NewMailingList<'a> {
+    pub name: &'a str,
+    pub description: &'a str,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = mailing_lists)]
+pub struct UpdateMailingList<'a> {
+    pub name: Option<&'a str>,
+    pub description: Option<&'a str>,
+}
+
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = mailing_list_subscribers)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(dead_code)]
+pub struct MailingListSubscriber {
+    pub id: i32,
+    pub created_at: Option<SystemTime>,
+    pub updated_at: Option<SystemTime>,
+    pub email: String,
+    pub name: String,
+    pub mailing_list_id: i32,
+    pub locale: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = mailing_list_subscribers)]
+pub struct NewMailingListSubscriber<'a> {
+    pub email: &'a str,
+    pub name: &'a str,
+    pub mailing_list_id: i32,
+    pub locale: Option<&'a str>,
+}
+
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = api_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(dead_code)]
+pub struct ApiKey {
+    pub id: i32,
+    pub created_at: SystemTime,
+    pub name: String,
+    pub key_prefix: String,
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<SystemTime>,
+    pub revoked_at: Option<SystemTime>,
+    pub allowed_senders: Vec<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct NewApiKey<'a> {
+    pub name: &'a str,
+    pub key_prefix: &'a str,
+    pub key_hash: &'a str,
+    pub scopes: &'a [String],
+    pub allowed_senders: &'a [String],
+}
+
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = suppressions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Suppression {
+    pub id: i32,
+    pub created_at: SystemTime,
+    pub email: String,
+    pub reason: String,
+    pub mailing_list_id: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = suppressions)]
+pub struct NewSuppression<'a> {
+    pub email: &'a str,
+    pub reason: &'a str,
+    pub mailing_list_id: Option<i32>,
 }
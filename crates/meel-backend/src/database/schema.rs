@@ -1,5 +1,19 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        name -> Text,
+        key_prefix -> Text,
+        key_hash -> Text,
+        scopes -> Array<Text>,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        allowed_senders -> Array<Text>,
+    }
+}
+
 diesel::table! {
     mail_attachments (id) {
         id -> Int4,
@@ -13,10 +27,57 @@ diesel::table! {
 }
 
 diesel::table! {
-    mails (id) {
+    mail_events (id) {
+        id -> Int4,
+        mail_id -> Int4,
+        created_at -> Timestamp,
+        event_type -> Text,
+        smtp_code -> Nullable<Int4>,
+        message -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    mail_recipients (id) {
+        id -> Int4,
+        mail_id -> Int4,
+        created_at -> Timestamp,
+        kind -> Text,
+        address -> Text,
+        status -> Text,
+        smtp_code -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        normalized_address -> Text,
+    }
+}
+
+diesel::table! {
+    mailing_list_subscribers (id) {
         id -> Int4,
         created_at -> Nullable<Timestamp>,
         updated_at -> Nullable<Timestamp>,
+        email -> Text,
+        name -> Text,
+        mailing_list_id -> Int4,
+        locale -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    mailing_lists (id) {
+        id -> Int4,
+        created_at -> Nullable<Timestamp>,
+        updated_at -> Nullable<Timestamp>,
+        name -> Text,
+        description -> Text,
+    }
+}
+
+diesel::table! {
+    mails (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
         sender -> Text,
         recipient -> Text,
         subject -> Text,
@@ -27,12 +88,40 @@ diesel::table! {
         sent_at -> Nullable<Timestamp>,
         scheduled_at -> Timestamp,
         reply_to -> Nullable<Text>,
+        locked_until -> Nullable<Timestamp>,
+        next_attempt_at -> Nullable<Timestamp>,
+        last_error -> Nullable<Text>,
+        status -> Text,
+        template -> Nullable<Text>,
+        headers -> Jsonb,
+        tags -> Array<Text>,
+        metadata -> Jsonb,
+    }
+}
+
+diesel::table! {
+    suppressions (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        email -> Text,
+        reason -> Text,
+        mailing_list_id -> Nullable<Int4>,
     }
 }
 
 diesel::joinable!(mail_attachments -> mails (mail_id));
+diesel::joinable!(mail_events -> mails (mail_id));
+diesel::joinable!(mail_recipients -> mails (mail_id));
+diesel::joinable!(mailing_list_subscribers -> mailing_lists (mailing_list_id));
+diesel::joinable!(suppressions -> mailing_lists (mailing_list_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
     mail_attachments,
+    mail_events,
+    mail_recipients,
+    mailing_list_subscribers,
+    mailing_lists,
     mails,
+    suppressions,
 );
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use lettre::message::dkim::{
+    DkimCanonicalization, DkimCanonicalizationType, DkimConfig, DkimSigningAlgorithm,
+    DkimSigningKey,
+};
+use lettre::message::header::HeaderName;
+use lettre::Message;
+
+/// Headers covered by the signature. Headers a mail doesn't have are signed as empty, so they
+/// can't be added along the way either.
+const SIGNED_HEADERS: [&str; 8] = [
+    "From",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Subject",
+    "Date",
+    "List-Unsubscribe",
+    "List-Unsubscribe-Post",
+];
+
+fn parse_algorithm(value: &str) -> Option<DkimSigningAlgorithm> {
+    match value.trim().to_lowercase().as_str() {
+        "rsa" => Some(DkimSigningAlgorithm::Rsa),
+        "ed25519" => Some(DkimSigningAlgorithm::Ed25519),
+        _ => None,
+    }
+}
+
+/// A private key as configured, before it is parsed.
+struct DkimKey {
+    domain: String,
+    selector: String,
+    private_key: String,
+    algorithm: DkimSigningAlgorithm,
+}
+
+/// Create the signing configuration for a key. RSA keys are expected in PKCS#1 PEM format,
+/// Ed25519 keys as the base64 encoded 32 byte private key.
+fn create_config(key: &DkimKey) -> Result<DkimConfig, String> {
+    let signing_key =
+        DkimSigningKey::new(key.private_key.trim(), key.algorithm).map_err(|err| {
+            format!(
+                "Invalid {} DKIM key for {}: {}",
+                key.algorithm, key.domain, err
+            )
+        })?;
+
+    Ok(DkimConfig::new(
+        key.selector.clone(),
+        key.domain.clone(),
+        signing_key,
+        SIGNED_HEADERS
+            .iter()
+            .map(|name| HeaderName::new_from_ascii_str(name))
+            .collect(),
+        DkimCanonicalization {
+            header: DkimCanonicalizationType::Relaxed,
+            body: DkimCanonicalizationType::Relaxed,
+        },
+    ))
+}
+
+/// The DKIM keys to sign outgoing mail with, by sender domain.
+pub struct DkimKeys {
+    configs: HashMap<String, DkimConfig>,
+}
+
+impl DkimKeys {
+    /// Load the keys from `MEEL_DATA_DIRECTORY/dkim/<domain>/<selector>.pem` (RSA) and
+    /// `<selector>.ed25519` files, and from the `MEEL_DKIM_*` variables. Keys that fail to load
+    /// are logged and skipped, mails from those domains are sent unsigned.
+    pub fn load() -> Self {
+        let mut configs = HashMap::new();
+
+        let directory = format!(
+            "{}/dkim",
+            meel_utils::env::get_var("MEEL_DATA_DIRECTORY", Some("./data")).unwrap()
+        );
+
+        // The key from the environment takes precedence over a file for the same domain.
+        let keys = read_key_files(Path::new(&directory))
+            .into_iter()
+            .chain(read_key_from_env());
+
+        for key in keys {
+            match create_config(&key) {
+                Ok(config) => {
+                    configs.insert(key.domain, config);
+                }
+                Err(err) => tracing::error!("{}", err),
+            }
+        }
+
+        let mut domains: Vec<&String> = configs.keys().collect();
+        domains.sort();
+        if domains.is_empty() {
+            tracing::info!("No DKIM keys configured, mails are sent unsigned");
+        } else {
+            tracing::info!("Loaded DKIM keys for {:?}", domains);
+        }
+
+        Self { configs }
+    }
+
+    /// Sign a message with the key of the sender domain. Subdomains without a key of their own
+    /// use the key of their parent domain, which still aligns for DMARC.
+    pub fn sign(&self, message: &mut Message, sender_domain: &str) {
+        if let Some(config) = find_config(&self.configs, sender_domain) {
+            message.sign(config);
+        }
+    }
+}
+
+fn find_config<'a, T>(configs: &'a HashMap<String, T>, domain: &str) -> Option<&'a T> {
+    let mut domain = domain.trim_end_matches('.').to_lowercase();
+
+    loop {
+        if let Some(config) = configs.get(&domain) {
+            return Some(config);
+        }
+
+        // Stop before trying the top level domain on its own.
+        match domain.split_once('.') {
+            Some((_, parent)) if parent.contains('.') => domain = parent.to_string(),
+            _ => return None,
+        }
+    }
+}
+
+#[test]
+fn test_find_config() {
+    let configs = HashMap::from([
+        ("example.com".to_string(), 1),
+        ("mail.example.org".to_string(), 2),
+    ]);
+
+    assert_eq!(find_config(&configs, "Example.com"), Some(&1));
+    assert_eq!(find_config(&configs, "news.example.com"), Some(&1));
+    assert_eq!(find_config(&configs, "mail.example.org"), Some(&2));
+    assert_eq!(find_config(&configs, "example.org"), None);
+    assert_eq!(find_config(&configs, "com"), None);
+}
+
+#[test]
+fn test_sign_message() {
+    let key = DkimKey {
+        domain: "example.com".to_string(),
+        selector: "meel".to_string(),
+        // Any 32 bytes make a valid Ed25519 key.
+        private_key: "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=".to_string(),
+        algorithm: DkimSigningAlgorithm::Ed25519,
+    };
+    let dkim_keys = DkimKeys {
+        configs: HashMap::from([(key.domain.clone(), create_config(&key).unwrap())]),
+    };
+
+    let build_message = || {
+        Message::builder()
+            .from("noreply@example.com".parse().unwrap())
+            .to("boris@example.org".parse().unwrap())
+            .subject("Hello world")
+            .body("Hello".to_string())
+            .unwrap()
+    };
+
+    let mut message = build_message();
+    dkim_keys.sign(&mut message, "example.com");
+    let signature = message.headers().get_raw("DKIM-Signature").unwrap();
+    assert!(signature.contains("a=ed25519-sha256"));
+    assert!(signature.contains("d=example.com"));
+    assert!(signature.contains("s=meel"));
+
+    let mut message = build_message();
+    dkim_keys.sign(&mut message, "example.net");
+    assert!(message.headers().get_raw("DKIM-Signature").is_none());
+}
+
+/// Read `<domain>/<selector>.<pem|ed25519>` files. A domain should have a single key, when there
+/// are more the first one by name is used.
+fn read_key_files(directory: &Path) -> Vec<DkimKey> {
+    let mut keys = vec![];
+
+    let Ok(domain_entries) = fs::read_dir(directory) else {
+        return keys;
+    };
+
+    for domain_entry in domain_entries.flatten() {
+        let domain_path = domain_entry.path();
+        let Some(domain) = domain_path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Ok(key_entries) = fs::read_dir(&domain_path) else {
+            continue;
+        };
+
+        let mut key_paths: Vec<_> = key_entries.flatten().map(|entry| entry.path()).collect();
+        key_paths.sort();
+
+        for key_path in key_paths {
+            let algorithm = match key_path
+                .extension()
+                .and_then(|extension| extension.to_str())
+            {
+                Some("pem") => DkimSigningAlgorithm::Rsa,
+                Some("ed25519") => DkimSigningAlgorithm::Ed25519,
+                _ => continue,
+            };
+            let Some(selector) = key_path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            match fs::read_to_string(&key_path) {
+                Ok(private_key) => {
+                    keys.push(DkimKey {
+                        domain: domain.to_lowercase(),
+                        selector: selector.to_string(),
+                        private_key,
+                        algorithm,
+                    });
+                    break;
+                }
+                Err(err) => tracing::error!("Failed to read DKIM key {:?}: {}", key_path, err),
+            }
+        }
+    }
+
+    keys
+}
+
+/// Read a single key from `MEEL_DKIM_DOMAIN`, `MEEL_DKIM_SELECTOR`, `MEEL_DKIM_PRIVATE_KEY` and
+/// `MEEL_DKIM_ALGORITHM`, which is handy when the data directory isn't mounted.
+fn read_key_from_env() -> Option<DkimKey> {
+    let domain = meel_utils::env::get_var("MEEL_DKIM_DOMAIN", None)?;
+    let selector = meel_utils::env::get_var("MEEL_DKIM_SELECTOR", None)?;
+    // Allow the line breaks of a PEM key to be written as `\n`, as env files are line based.
+    let private_key = meel_utils::env::get_var("MEEL_DKIM_PRIVATE_KEY", None)?.replace("\\n", "\n");
+
+    if domain.trim().is_empty() || selector.trim().is_empty() || private_key.trim().is_empty() {
+        return None;
+    }
+
+    let algorithm_name = meel_utils::env::get_var("MEEL_DKIM_ALGORITHM", Some("rsa")).unwrap();
+    let Some(algorithm) = parse_algorithm(&algorithm_name) else {
+        tracing::error!(
+            "Unknown MEEL_DKIM_ALGORITHM `{}`, expected `rsa` or `ed25519`",
+            algorithm_name
+        );
+        return None;
+    };
+
+    Some(DkimKey {
+        domain: domain.trim().to_lowercase(),
+        selector: selector.trim().to_string(),
+        private_key,
+        algorithm,
+    })
+}
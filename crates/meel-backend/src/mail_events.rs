@@ -0,0 +1,74 @@
+use std::time::SystemTime;
+
+use diesel::{PgConnection, QueryResult, RunQueryDsl};
+
+use crate::database::models::NewMailEvent;
+use crate::database::schema::mail_events;
+
+/// Something that happened to a mail during its lifetime.
+#[derive(Clone, Copy)]
+pub enum MailEventType {
+    Rendered,
+    Queued,
+    AttemptFailed,
+    Sent,
+    Expired,
+    Failed,
+    Updated,
+    Cancelled,
+}
+
+impl MailEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailEventType::Rendered => "rendered",
+            MailEventType::Queued => "queued",
+            MailEventType::AttemptFailed => "attempt_failed",
+            MailEventType::Sent => "sent",
+            MailEventType::Expired => "expired",
+            MailEventType::Failed => "failed",
+            MailEventType::Updated => "updated",
+            MailEventType::Cancelled => "cancelled",
+        }
+    }
+}
+
+pub fn record_event(
+    conn: &mut PgConnection,
+    mail_id: i32,
+    event_type: MailEventType,
+    smtp_code: Option<u16>,
+    message: Option<&str>,
+) -> QueryResult<()> {
+    insert_event(conn, mail_id, event_type, None, smtp_code, message)
+}
+
+/// Record an event that happened before the mail was stored, such as rendering its template.
+pub fn record_event_at(
+    conn: &mut PgConnection,
+    mail_id: i32,
+    event_type: MailEventType,
+    created_at: SystemTime,
+) -> QueryResult<()> {
+    insert_event(conn, mail_id, event_type, Some(created_at), None, None)
+}
+
+fn insert_event(
+    conn: &mut PgConnection,
+    mail_id: i32,
+    event_type: MailEventType,
+    created_at: Option<SystemTime>,
+    smtp_code: Option<u16>,
+    message: Option<&str>,
+) -> QueryResult<()> {
+    diesel::insert_into(mail_events::table)
+        .values(&NewMailEvent {
+            mail_id,
+            created_at,
+            event_type: event_type.as_str(),
+            smtp_code: smtp_code.map(i32::from),
+            message,
+        })
+        .execute(conn)
+        .map(|_| ())
+}
@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, HashMap};
+
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::Message;
+
+/// Headers that may be set through the API besides custom `X-` headers. Everything else, like the
+/// address, content and routing headers, is managed by Meel itself.
+const ALLOWED_HEADERS: [&str; 13] = [
+    "Auto-Submitted",
+    "Comments",
+    "Feedback-ID",
+    "In-Reply-To",
+    "Keywords",
+    "List-Archive",
+    "List-Help",
+    "List-Id",
+    "List-Owner",
+    "List-Post",
+    "List-Subscribe",
+    "Precedence",
+    "References",
+];
+
+/// The maximum length of a header line, as defined by RFC 5322.
+const MAX_HEADER_LENGTH: usize = 998;
+
+fn is_allowed_header(name: &str) -> bool {
+    let lowercase_name = name.to_ascii_lowercase();
+
+    (lowercase_name.starts_with("x-") && lowercase_name.len() > 2)
+        || ALLOWED_HEADERS
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(name))
+}
+
+fn is_valid_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 76
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_graphic() && byte != b':')
+}
+
+fn is_valid_header_value(value: &str) -> bool {
+    value.len() <= MAX_HEADER_LENGTH && !value.contains(['\r', '\n'])
+}
+
+/// Format the `List-Unsubscribe` header, which lists each URI between angle brackets.
+fn format_list_unsubscribe(
+    uris: &[String],
+    errors: &mut HashMap<String, String>,
+) -> Option<String> {
+    for (index, uri) in uris.iter().enumerate() {
+        let lowercase_uri = uri.to_ascii_lowercase();
+
+        if !lowercase_uri.starts_with("https://") && !lowercase_uri.starts_with("mailto:") {
+            errors.insert(
+                format!("list_unsubscribe[{index}]"),
+                "Expected an `https:` URL or a `mailto:` address".to_string(),
+            );
+        } else if uri.contains(|c: char| c.is_whitespace() || c == '<' || c == '>') {
+            errors.insert(
+                format!("list_unsubscribe[{index}]"),
+                "URIs can't contain whitespace or angle brackets".to_string(),
+            );
+        }
+    }
+
+    if uris.is_empty() {
+        return None;
+    }
+
+    Some(
+        uris.iter()
+            .map(|uri| format!("<{uri}>"))
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+}
+
+/// Validate the extra headers of a mail request and combine them with the `List-Unsubscribe`
+/// headers, in the form they are stored on the mail. Problems are recorded in `errors`.
+pub fn collect_headers(
+    headers: &HashMap<String, String>,
+    list_unsubscribe: &[String],
+    one_click: bool,
+    errors: &mut HashMap<String, String>,
+) -> BTreeMap<String, String> {
+    let mut collected_headers = BTreeMap::new();
+
+    // Go through the names in order, so the same one of two duplicates is reported every time.
+    let mut names: Vec<&String> = headers.keys().collect();
+    names.sort();
+
+    for name in names {
+        let value = &headers[name];
+        let field = format!("headers.{name}");
+
+        if !is_valid_header_name(name) {
+            errors.insert(field, "Invalid header name".to_string());
+        } else if !is_allowed_header(name) {
+            errors.insert(
+                field,
+                "Only `X-` headers and a few list and threading headers can be set".to_string(),
+            );
+        } else if !is_valid_header_value(value) {
+            errors.insert(
+                field,
+                format!(
+                    "Header values can't contain line breaks or exceed {MAX_HEADER_LENGTH} characters"
+                ),
+            );
+        } else if collected_headers
+            .keys()
+            .any(|collected: &String| collected.eq_ignore_ascii_case(name))
+        {
+            errors.insert(field, "Duplicate header".to_string());
+        } else {
+            collected_headers.insert(name.clone(), value.clone());
+        }
+    }
+
+    if let Some(value) = format_list_unsubscribe(list_unsubscribe, errors) {
+        collected_headers.insert("List-Unsubscribe".to_string(), value);
+    }
+
+    if one_click {
+        // RFC 8058 requires an HTTPS URL to POST to.
+        if list_unsubscribe
+            .iter()
+            .any(|uri| uri.to_ascii_lowercase().starts_with("https://"))
+        {
+            collected_headers.insert(
+                "List-Unsubscribe-Post".to_string(),
+                "List-Unsubscribe=One-Click".to_string(),
+            );
+        } else {
+            errors.insert(
+                "list_unsubscribe_one_click".to_string(),
+                "One-click unsubscribe needs an `https:` URL in `list_unsubscribe`".to_string(),
+            );
+        }
+    }
+
+    collected_headers
+}
+
+#[test]
+fn test_collect_headers() {
+    let mut errors = HashMap::new();
+    let headers = collect_headers(
+        &HashMap::from([
+            ("X-Campaign".to_string(), "spring-sale".to_string()),
+            ("Precedence".to_string(), "bulk".to_string()),
+        ]),
+        &[
+            "mailto:unsubscribe@example.com".to_string(),
+            "https://example.com/unsubscribe/abc".to_string(),
+        ],
+        true,
+        &mut errors,
+    );
+
+    assert!(errors.is_empty());
+    assert_eq!(headers.get("X-Campaign").unwrap(), "spring-sale");
+    assert_eq!(
+        headers.get("List-Unsubscribe").unwrap(),
+        "<mailto:unsubscribe@example.com>, <https://example.com/unsubscribe/abc>"
+    );
+    assert_eq!(
+        headers.get("List-Unsubscribe-Post").unwrap(),
+        "List-Unsubscribe=One-Click"
+    );
+
+    let mut errors = HashMap::new();
+    collect_headers(
+        &HashMap::from([
+            ("From".to_string(), "ceo@example.com".to_string()),
+            ("X-Bad Name".to_string(), "value".to_string()),
+            (
+                "X-Injected".to_string(),
+                "a\r\nBcc: b@example.com".to_string(),
+            ),
+        ]),
+        &["mailto:unsubscribe@example.com".to_string()],
+        true,
+        &mut errors,
+    );
+
+    assert_eq!(errors.len(), 4);
+    assert!(errors.contains_key("headers.From"));
+    assert!(errors.contains_key("headers.X-Bad Name"));
+    assert!(errors.contains_key("headers.X-Injected"));
+    assert!(errors.contains_key("list_unsubscribe_one_click"));
+
+    for _ in 0..10 {
+        let mut errors = HashMap::new();
+        let headers = collect_headers(
+            &HashMap::from([
+                ("x-campaign".to_string(), "b".to_string()),
+                ("X-Campaign".to_string(), "a".to_string()),
+            ]),
+            &[],
+            false,
+            &mut errors,
+        );
+
+        assert_eq!(headers.get("X-Campaign").unwrap(), "a");
+        assert!(errors.contains_key("headers.x-campaign"));
+    }
+}
+
+/// Add the stored headers of a mail to a built message. The headers were validated when the mail
+/// was submitted, anything that doesn't parse anymore is skipped.
+pub fn apply_headers(message: &mut Message, headers: &serde_json::Value) {
+    let Some(headers) = headers.as_object() else {
+        return;
+    };
+
+    for (name, value) in headers {
+        let (Ok(header_name), Some(value)) =
+            (HeaderName::new_from_ascii(name.clone()), value.as_str())
+        else {
+            tracing::warn!("Skipping invalid header `{}`", name);
+            continue;
+        };
+
+        message
+            .headers_mut()
+            .insert_raw(HeaderValue::new(header_name, value.to_string()));
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl};
+
+use crate::database::models::{MailRecipient, NewMailRecipient};
+use crate::database::schema::mail_recipients;
+use crate::unsubscribe;
+
+/// Which header a recipient is addressed in. Bcc recipients are only part of the envelope.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecipientKind {
+    To,
+    Cc,
+    Bcc,
+}
+
+impl RecipientKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecipientKind::To => "to",
+            RecipientKind::Cc => "cc",
+            RecipientKind::Bcc => "bcc",
+        }
+    }
+}
+
+/// The delivery status of a single recipient of a mail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecipientStatus {
+    Pending,
+    Sent,
+    Failed,
+}
+
+impl RecipientStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecipientStatus::Pending => "pending",
+            RecipientStatus::Sent => "sent",
+            RecipientStatus::Failed => "failed",
+        }
+    }
+}
+
+pub fn insert_recipients(
+    conn: &mut PgConnection,
+    mail_id: i32,
+    recipients: &[(RecipientKind, String)],
+) -> QueryResult<Vec<MailRecipient>> {
+    let new_recipients: Vec<NewMailRecipient> = recipients
+        .iter()
+        .map(|(kind, address)| NewMailRecipient {
+            mail_id,
+            kind: kind.as_str(),
+            address,
+            normalized_address: unsubscribe::normalize_address(address),
+        })
+        .collect();
+
+    diesel::insert_into(mail_recipients::table)
+        .values(&new_recipients)
+        .get_results(conn)
+}
+
+/// Load the recipients of several mails at once, grouped by mail id.
+pub fn load_recipients(
+    conn: &mut PgConnection,
+    mail_ids: &[i32],
+) -> QueryResult<HashMap<i32, Vec<MailRecipient>>> {
+    let recipients = mail_recipients::table
+        .filter(mail_recipients::mail_id.eq_any(mail_ids))
+        .order(mail_recipients::id.asc())
+        .load::<MailRecipient>(conn)?;
+
+    let mut recipients_by_mail: HashMap<i32, Vec<MailRecipient>> = HashMap::new();
+    for recipient in recipients {
+        recipients_by_mail
+            .entry(recipient.mail_id)
+            .or_default()
+            .push(recipient);
+    }
+
+    Ok(recipients_by_mail)
+}
+
+/// Mark the recipients of a mail that haven't failed as sent, with the reply of the server that
+/// accepted the message.
+pub fn mark_recipients_sent(
+    conn: &mut PgConnection,
+    mail_id: i32,
+    smtp_code: Option<u16>,
+) -> QueryResult<()> {
+    diesel::update(
+        mail_recipients::table
+            .filter(mail_recipients::mail_id.eq(mail_id))
+            .filter(mail_recipients::status.eq(RecipientStatus::Pending.as_str())),
+    )
+    .set((
+        mail_recipients::status.eq(RecipientStatus::Sent.as_str()),
+        mail_recipients::smtp_code.eq(smtp_code.map(i32::from)),
+        mail_recipients::last_error.eq(None::<String>),
+    ))
+    .execute(conn)?;
+
+    Ok(())
+}
+
+/// Record the reply of the server to a single recipient it refused.
+pub fn update_recipient_status(
+    conn: &mut PgConnection,
+    recipient_id: i32,
+    status: RecipientStatus,
+    smtp_code: Option<u16>,
+    error: &str,
+) -> QueryResult<()> {
+    diesel::update(mail_recipients::table.find(recipient_id))
+        .set((
+            mail_recipients::status.eq(status.as_str()),
+            mail_recipients::smtp_code.eq(smtp_code.map(i32::from)),
+            mail_recipients::last_error.eq(error),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
@@ -1,85 +1,214 @@
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration as StdDuration, SystemTime};
 
-use axum::http::StatusCode;
 use chrono::{Duration, NaiveDateTime, Utc};
-use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
-use lettre::message::{header, Mailbox, MultiPart, SinglePart};
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::{Message, SmtpTransport, Transport};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+    SelectableHelper,
+};
+use lettre::message::{header, Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::Message;
 
-use crate::database::models::Mail;
+use crate::database::models::{Mail, MailAttachment, MailRecipient};
+use crate::database::schema::mail_attachments;
 use crate::database::schema::mails::dsl::mails;
-use crate::database::schema::mails::{id, scheduled_at, send_attempts, sent_at};
+use crate::database::schema::mails::{
+    id, last_error, locked_until, next_attempt_at, priority, scheduled_at, send_attempts, sent_at,
+    status,
+};
 use crate::database::ConnectionPool;
+use crate::dkim::DkimKeys;
+use crate::mail_events::{record_event, MailEventType};
+use crate::mail_headers;
+use crate::mail_recipients::{
+    load_recipients, mark_recipients_sent, update_recipient_status, RecipientStatus,
+};
+use crate::mail_status::MailStatus;
+use crate::mail_transport::{MailTransport, MailTransportError};
+use crate::rate_limiter::OutboundRateLimits;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 
-async fn fetch_mails(pool: Arc<ConnectionPool>) -> Result<Vec<Mail>, StatusCode> {
-    let mut conn = match pool.get() {
-        Ok(conn) => conn,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
-
+fn get_max_send_attempts() -> i32 {
     const DEFAULT_MAX_SEND_ATTEMPTS: i32 = 10;
-    let max_send_attempts: i32 = meel_utils::env::get_var(
+    meel_utils::env::get_var(
         "MEEL_MAX_SEND_ATTEMPTS",
         Some(&DEFAULT_MAX_SEND_ATTEMPTS.to_string()),
     )
-    .ok_or(&DEFAULT_MAX_SEND_ATTEMPTS.to_string())
     .unwrap()
     .parse()
-    .unwrap_or(DEFAULT_MAX_SEND_ATTEMPTS);
+    .unwrap_or(DEFAULT_MAX_SEND_ATTEMPTS)
+}
 
-    let mut scheduled_mails = match mails
-        .filter(scheduled_at.lt(SystemTime::now()))
-        .filter(sent_at.is_null())
-        .filter(send_attempts.lt(max_send_attempts))
-        .load::<Mail>(&mut conn)
-    {
-        Ok(scheduled_mails) => scheduled_mails,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
+/// Calculate how long to wait before retrying a mail that failed `attempts` times.
+///
+/// The delay doubles with every attempt up to `max_delay`, and is then spread by up to `jitter`
+/// (a fraction of the delay) in either direction, so mails that failed together during an outage
+/// don't all retry in the same tick. `random` is expected to be in the range `0.0..1.0`.
+fn calculate_retry_delay(
+    attempts: i32,
+    base_delay: u64,
+    max_delay: u64,
+    jitter: f64,
+    random: f64,
+) -> StdDuration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 32) as u32;
+    let delay = base_delay
+        .saturating_mul(2u64.saturating_pow(exponent))
+        .min(max_delay) as f64;
 
-    scheduled_mails.sort_by(|a, b| {
-        b.priority
-            .cmp(&a.priority)
-            .then_with(|| a.scheduled_at.cmp(&b.scheduled_at))
-            .then_with(|| a.send_attempts.cmp(&b.send_attempts))
-    });
+    let jitter = jitter.clamp(0.0, 1.0);
+    let spread = delay * jitter * (random * 2.0 - 1.0);
 
-    Ok(scheduled_mails)
+    StdDuration::from_secs_f64((delay + spread).max(0.0))
 }
 
-fn get_smtp_transport() -> Result<SmtpTransport, String> {
-    let smtp_relay = meel_utils::env::get_var("MEEL_SMTP_RELAY", None);
+fn get_retry_delay(attempts: i32) -> StdDuration {
+    const DEFAULT_BASE_DELAY: u64 = 30;
+    let base_delay = meel_utils::env::get_var(
+        "MEEL_RETRY_BASE_DELAY",
+        Some(&DEFAULT_BASE_DELAY.to_string()),
+    )
+    .unwrap()
+    .parse::<u64>()
+    .unwrap_or(DEFAULT_BASE_DELAY);
 
-    let mailer = if let Some(smtp_relay) = smtp_relay {
-        let smtp_username = match meel_utils::env::get_var("MEEL_SMTP_USERNAME", None) {
-            Some(username) => username,
-            None => return Err("MEEL_SMTP_USERNAME must be set".to_string()),
-        };
+    const DEFAULT_MAX_DELAY: u64 = 60 * 60;
+    let max_delay =
+        meel_utils::env::get_var("MEEL_RETRY_MAX_DELAY", Some(&DEFAULT_MAX_DELAY.to_string()))
+            .unwrap()
+            .parse::<u64>()
+            .unwrap_or(DEFAULT_MAX_DELAY);
 
-        let smtp_password = match meel_utils::env::get_var("MEEL_SMTP_PASSWORD", None) {
-            Some(password) => password,
-            None => return Err("MEEL_SMTP_PASSWORD must be set".to_string()),
-        };
+    const DEFAULT_JITTER: f64 = 0.2;
+    let jitter = meel_utils::env::get_var("MEEL_RETRY_JITTER", Some(&DEFAULT_JITTER.to_string()))
+        .unwrap()
+        .parse::<f64>()
+        .unwrap_or(DEFAULT_JITTER);
+
+    calculate_retry_delay(attempts, base_delay, max_delay, jitter, fastrand::f64())
+}
+
+#[test]
+fn test_calculate_retry_delay() {
+    // Without jitter the delay doubles with every attempt.
+    assert_eq!(calculate_retry_delay(1, 30, 3600, 0.0, 0.5).as_secs(), 30);
+    assert_eq!(calculate_retry_delay(2, 30, 3600, 0.0, 0.5).as_secs(), 60);
+    assert_eq!(calculate_retry_delay(4, 30, 3600, 0.0, 0.5).as_secs(), 240);
+
+    // And is capped at the maximum delay.
+    assert_eq!(
+        calculate_retry_delay(10, 30, 3600, 0.0, 0.5).as_secs(),
+        3600
+    );
+    assert_eq!(
+        calculate_retry_delay(1000, 30, 3600, 0.0, 0.5).as_secs(),
+        3600
+    );
+
+    // Jitter spreads the delay in both directions.
+    assert_eq!(calculate_retry_delay(2, 30, 3600, 0.5, 0.0).as_secs(), 30);
+    assert_eq!(calculate_retry_delay(2, 30, 3600, 0.5, 0.5).as_secs(), 60);
+    assert_eq!(
+        calculate_retry_delay(2, 30, 3600, 0.5, 0.9999).as_secs(),
+        89
+    );
+}
+
+/// Claim a batch of due mails for this worker.
+///
+/// The rows are selected with `FOR UPDATE SKIP LOCKED`, moved to `sending` and marked with a
+/// `locked_until` lease, so overlapping runs and other Meel instances skip them. If a worker dies
+/// mid-batch, the lease expires and the mails are picked up again.
+fn claim_mails(
+    conn: &mut PgConnection,
+    batch_size: i64,
+) -> Result<Vec<Mail>, diesel::result::Error> {
+    const DEFAULT_LOCK_TIMEOUT: i64 = 300;
+    let lock_timeout = meel_utils::env::get_var(
+        "MEEL_SCHEDULER_LOCK_TIMEOUT",
+        Some(&DEFAULT_LOCK_TIMEOUT.to_string()),
+    )
+    .unwrap()
+    .parse::<u64>()
+    .unwrap_or(DEFAULT_LOCK_TIMEOUT as u64);
+
+    let max_send_attempts = get_max_send_attempts();
+
+    conn.transaction(|conn| {
+        let now = SystemTime::now();
 
-        let creds = Credentials::new(smtp_username, smtp_password);
+        let claimed_ids = mails
+            .select(id)
+            .filter(status.eq_any([
+                MailStatus::Queued.as_str(),
+                MailStatus::Retrying.as_str(),
+                MailStatus::Sending.as_str(),
+            ]))
+            .filter(scheduled_at.lt(now))
+            .filter(sent_at.is_null())
+            .filter(send_attempts.lt(max_send_attempts))
+            // A mail that is still `sending` with an expired lease belongs to a worker that died.
+            .filter(locked_until.is_null().or(locked_until.lt(now)))
+            .filter(next_attempt_at.is_null().or(next_attempt_at.lt(now)))
+            .order((priority.desc(), scheduled_at.asc(), send_attempts.asc()))
+            .limit(batch_size)
+            .for_update()
+            .skip_locked()
+            .load::<i32>(conn)?;
 
-        match SmtpTransport::relay(&smtp_relay) {
-            Ok(mailer) => mailer.credentials(creds).build(),
-            Err(_) => return Err("Failed to build mailer".to_string()),
+        if claimed_ids.is_empty() {
+            return Ok(vec![]);
         }
-    } else {
-        let transport_domain = meel_utils::env::get_var("MEEL_TRANSPORT_DOMAIN", Some("mailhog"));
-        let transport_port = meel_utils::env::get_var("MEEL_TRANSPORT_PORT", Some("1025"));
 
-        // We can use unwrap safely here, as we have a fallback set above.
-        SmtpTransport::builder_dangerous(transport_domain.unwrap())
-            .port(transport_port.unwrap().parse().unwrap_or(1025))
-            .build()
-    };
+        let mut claimed_mails = diesel::update(mails.filter(id.eq_any(&claimed_ids)))
+            .set((
+                status.eq(MailStatus::Sending.as_str()),
+                locked_until.eq(now + StdDuration::from_secs(lock_timeout)),
+            ))
+            .returning(Mail::as_returning())
+            .get_results(conn)?;
+
+        claimed_mails.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then_with(|| a.scheduled_at.cmp(&b.scheduled_at))
+                .then_with(|| a.send_attempts.cmp(&b.send_attempts))
+        });
 
-    Ok(mailer)
+        Ok(claimed_mails)
+    })
+}
+
+/// Fail the mails that used up their send attempts without being delivered. The scheduler never
+/// claims those, this catches mails from before the limit existed, or from when it was higher.
+fn expire_exhausted_mails(conn: &mut PgConnection) -> Result<Vec<i32>, diesel::result::Error> {
+    let max_send_attempts = get_max_send_attempts();
+
+    conn.transaction(|conn| {
+        let expired_ids = diesel::update(
+            mails
+                .filter(status.eq_any([MailStatus::Queued.as_str(), MailStatus::Retrying.as_str()]))
+                .filter(sent_at.is_null())
+                .filter(send_attempts.ge(max_send_attempts)),
+        )
+        .set(status.eq(MailStatus::Failed.as_str()))
+        .returning(id)
+        .get_results::<i32>(conn)?;
+
+        for expired_id in &expired_ids {
+            record_event(
+                conn,
+                *expired_id,
+                MailEventType::Expired,
+                None,
+                Some(&format!("Gave up after {max_send_attempts} attempts")),
+            )?;
+        }
+
+        Ok(expired_ids)
+    })
 }
 
 async fn remove_old_sent_emails(pool: Arc<ConnectionPool>) {
@@ -99,99 +228,612 @@ async fn remove_old_sent_emails(pool: Arc<ConnectionPool>) {
         0,
     );
 
-    match diesel::delete(mails.filter(sent_at.is_not_null().and(sent_at.lt(expiry_date))))
-        .execute(&mut conn)
-    {
-        Ok(affected_rows) => {
-            if affected_rows > 0 {
-                tracing::info!("Deleted {} expired sent emails", affected_rows);
+    let result = conn.transaction(|conn| {
+        let expired_mails = mails.filter(sent_at.is_not_null().and(sent_at.lt(expiry_date)));
+
+        let attachment_paths = mail_attachments::table
+            .select(mail_attachments::file_path)
+            .filter(mail_attachments::mail_id.eq_any(expired_mails.select(id)))
+            .load::<String>(conn)?;
+
+        let deleted_ids = diesel::delete(expired_mails)
+            .returning(id)
+            .get_results::<i32>(conn)?;
+
+        // Attachments of mailing list sends are shared, they stay until no mail uses them.
+        let used_paths = mail_attachments::table
+            .select(mail_attachments::file_path)
+            .filter(mail_attachments::file_path.eq_any(&attachment_paths))
+            .load::<String>(conn)?;
+
+        let unused_paths: Vec<String> = attachment_paths
+            .into_iter()
+            .filter(|path| !used_paths.contains(path))
+            .collect();
+
+        Ok::<(Vec<i32>, Vec<String>), diesel::result::Error>((deleted_ids, unused_paths))
+    });
+
+    match result {
+        Ok((deleted_ids, unused_paths)) => {
+            // The attachment rows are removed by the database, the files we clean up ourselves.
+            for unused_path in &unused_paths {
+                crate::attachments::remove_attachment_file(unused_path);
+            }
+            for deleted_id in &deleted_ids {
+                crate::attachments::remove_attachments(*deleted_id);
+            }
+
+            if !deleted_ids.is_empty() {
+                tracing::info!("Deleted {} expired sent emails", deleted_ids.len());
             }
         }
         Err(_) => tracing::error!("Failed to delete sent emails"),
     }
 }
 
-async fn send_mail(mail: Mail) -> Result<(), String> {
+fn build_attachment_part(attachment: &MailAttachment) -> Result<SinglePart, String> {
+    let content_type = match header::ContentType::parse(&attachment.file_type) {
+        Ok(content_type) => content_type,
+        Err(_) => return Err(format!("Invalid file type for {}", attachment.file_name)),
+    };
+
+    let contents = crate::attachments::read_attachment(&attachment.file_path)?;
+
+    Ok(Attachment::new(attachment.file_name.clone()).body(contents, content_type))
+}
+
+fn parse_recipient(address: &str) -> Result<Mailbox, MailTransportError> {
+    address.parse().map_err(|_| {
+        MailTransportError::permanent(format!("Failed to parse recipient email `{address}`"))
+    })
+}
+
+fn send_mail(
+    transport: &dyn MailTransport,
+    dkim_keys: &DkimKeys,
+    mail: Mail,
+    attachments: Vec<MailAttachment>,
+    recipients: &[MailRecipient],
+) -> Result<Option<u16>, MailTransportError> {
     let from_email: Mailbox = match mail.sender.parse() {
         Ok(email) => email,
-        Err(_) => return Err("Failed to parse sender email".to_string()),
+        Err(_) => {
+            return Err(MailTransportError::permanent(
+                "Failed to parse sender email".to_string(),
+            ))
+        }
     };
 
+    let sender_domain = from_email.email.domain().to_string();
+
     let reply_to_email: Mailbox = match mail.reply_to {
         Some(reply_to) => match reply_to.parse() {
             Ok(email) => email,
-            Err(_) => return Err("Failed to parse reply to email".to_string()),
+            Err(_) => {
+                return Err(MailTransportError::permanent(
+                    "Failed to parse reply to email".to_string(),
+                ))
+            }
         },
         None => from_email.clone(),
     };
 
-    let to_email: Mailbox = match mail.recipient.parse() {
-        Ok(email) => email,
-        Err(_) => return Err("Failed to parse recipient email".to_string()),
+    let mut builder = Message::builder().from(from_email).reply_to(reply_to_email);
+
+    // Mails queued before recipients were stored separately only have the `recipient` column.
+    if recipients.is_empty() {
+        builder = builder.to(parse_recipient(&mail.recipient)?);
+    }
+
+    // Lettre builds the envelope from all of these, and leaves the Bcc header out of the message.
+    // Recipients the server refused on an earlier attempt are left out.
+    for recipient in recipients.iter().filter(|recipient| !is_failed(recipient)) {
+        let mailbox = parse_recipient(&recipient.address)?;
+
+        builder = match recipient.kind.as_str() {
+            "cc" => builder.cc(mailbox),
+            "bcc" => builder.bcc(mailbox),
+            _ => builder.to(mailbox),
+        };
+    }
+
+    let alternative = MultiPart::alternative()
+        .singlepart(
+            SinglePart::builder()
+                .header(header::ContentType::TEXT_PLAIN)
+                .body(mail.text_body),
+        )
+        .singlepart(
+            SinglePart::builder()
+                .header(header::ContentType::TEXT_HTML)
+                .body(mail.html_body),
+        );
+
+    let body = if attachments.is_empty() {
+        alternative
+    } else {
+        let mut mixed = MultiPart::mixed().multipart(alternative);
+        for attachment in &attachments {
+            mixed = mixed.singlepart(
+                build_attachment_part(attachment).map_err(MailTransportError::transient)?,
+            );
+        }
+        mixed
     };
 
-    let email = match Message::builder()
-        .from(from_email)
-        .reply_to(reply_to_email)
-        .to(to_email)
-        .subject(mail.subject)
-        .multipart(
-            MultiPart::alternative()
-                .singlepart(
-                    SinglePart::builder()
-                        .header(header::ContentType::TEXT_PLAIN)
-                        .body(mail.text_body),
-                )
-                .singlepart(
-                    SinglePart::builder()
-                        .header(header::ContentType::TEXT_HTML)
-                        .body(mail.html_body),
-                ),
-        ) {
+    let mut email = match builder.subject(mail.subject).multipart(body) {
         Ok(email) => email,
-        Err(_) => return Err("Failed to build email".to_string()),
+        Err(_) => {
+            return Err(MailTransportError::permanent(
+                "Failed to build email".to_string(),
+            ))
+        }
+    };
+
+    mail_headers::apply_headers(&mut email, &mail.headers);
+    // Sign last, the signature covers the headers as they are sent.
+    dkim_keys.sign(&mut email, &sender_domain);
+
+    transport.send(&email)
+}
+
+#[test]
+fn test_send_mail() {
+    use crate::mail_transport::MemoryMailTransport;
+
+    let mail = Mail {
+        id: 1,
+        created_at: SystemTime::now(),
+        updated_at: None,
+        sender: "Meel <meel@example.com>".to_string(),
+        recipient: "ann@example.com".to_string(),
+        subject: "Welcome".to_string(),
+        html_body: "<p>Hello</p>".to_string(),
+        text_body: "Hello".to_string(),
+        send_attempts: 0,
+        priority: 0,
+        sent_at: None,
+        scheduled_at: SystemTime::now(),
+        reply_to: None,
+        locked_until: None,
+        next_attempt_at: None,
+        last_error: None,
+        status: MailStatus::Sending.as_str().to_string(),
+        template: None,
+        headers: serde_json::json!({ "X-Campaign": "spring" }),
+        tags: vec![],
+        metadata: serde_json::json!({}),
     };
+    let recipient =
+        |recipient_id: i32, kind: &str, address: &str, recipient_status: RecipientStatus| {
+            MailRecipient {
+                id: recipient_id,
+                mail_id: 1,
+                created_at: SystemTime::now(),
+                kind: kind.to_string(),
+                address: address.to_string(),
+                status: recipient_status.as_str().to_string(),
+                smtp_code: None,
+                last_error: None,
+                normalized_address: crate::unsubscribe::normalize_address(address),
+            }
+        };
+    let recipients = vec![
+        recipient(1, "to", "ann@example.com", RecipientStatus::Pending),
+        recipient(2, "bcc", "bob@example.com", RecipientStatus::Pending),
+        recipient(3, "cc", "carl@example.com", RecipientStatus::Failed),
+    ];
 
-    let mailer = get_smtp_transport()?;
+    let transport = MemoryMailTransport::default();
+    send_mail(&transport, &DkimKeys::load(), mail, vec![], &recipients).unwrap();
 
-    match mailer.send(&email) {
-        Ok(_) => Ok(()),
-        Err(err) => Err(err.to_string()),
+    let messages = transport.messages();
+    assert_eq!(messages.len(), 1);
+
+    let envelope: Vec<String> = messages[0]
+        .envelope()
+        .to()
+        .iter()
+        .map(|address| address.to_string())
+        .collect();
+    assert_eq!(envelope, vec!["ann@example.com", "bob@example.com"]);
+
+    let formatted = String::from_utf8(messages[0].formatted()).unwrap();
+    assert!(formatted.contains("Subject: Welcome"));
+    assert!(formatted.contains("X-Campaign: spring"));
+    assert!(!formatted.contains("bob@example.com"));
+    assert!(!formatted.contains("carl@example.com"));
+}
+
+/// The domain a mail is delivered to, used for per domain rate limits.
+fn get_recipient_domain(recipient: &str) -> String {
+    match recipient.parse::<Mailbox>() {
+        Ok(mailbox) => mailbox.email.domain().to_lowercase(),
+        Err(_) => recipient
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.trim_end_matches('>').to_lowercase())
+            .unwrap_or_default(),
     }
 }
 
-pub async fn send_mails(pool: Arc<ConnectionPool>) {
-    let scheduled_mails = match fetch_mails(pool.clone()).await {
-        Ok(scheduled_mails) => scheduled_mails,
+#[test]
+fn test_get_recipient_domain() {
+    assert_eq!(get_recipient_domain("boris@Example.com"), "example.com");
+    assert_eq!(get_recipient_domain("Boris <boris@gmail.com>"), "gmail.com");
+}
+
+/// Find the recipient a server refused from its error, relays mention the address in their reply
+/// to `RCPT TO`. Returns `None` when the error isn't about a single recipient.
+fn find_rejected_recipient<'a>(
+    error: &str,
+    recipients: &'a [MailRecipient],
+) -> Option<&'a MailRecipient> {
+    let error = error.to_lowercase();
+    let addresses: Vec<(&MailRecipient, String)> = recipients
+        .iter()
+        .filter(|recipient| !is_failed(recipient))
+        .map(|recipient| {
+            let address = match recipient.address.parse::<Mailbox>() {
+                Ok(mailbox) => mailbox.email.to_string(),
+                Err(_) => recipient.address.clone(),
+            };
+            (recipient, address.to_lowercase())
+        })
+        .collect();
+
+    let mut rejected: Vec<&(&MailRecipient, String)> = addresses
+        .iter()
+        .filter(|(_, address)| error.contains(address.as_str()))
+        .collect();
+
+    // `ann@example.com` also contains `n@example.com`, the brackets around it tell them apart.
+    if rejected.len() > 1 {
+        rejected.retain(|(_, address)| error.contains(&format!("<{address}>")));
+    }
+
+    match rejected.as_slice() {
+        [(recipient, _)] => Some(recipient),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_find_rejected_recipient() {
+    let recipient =
+        |recipient_id: i32, address: &str, recipient_status: RecipientStatus| MailRecipient {
+            id: recipient_id,
+            mail_id: 1,
+            created_at: SystemTime::now(),
+            kind: "to".to_string(),
+            address: address.to_string(),
+            status: recipient_status.as_str().to_string(),
+            smtp_code: None,
+            last_error: None,
+            normalized_address: crate::unsubscribe::normalize_address(address),
+        };
+    let recipients = vec![
+        recipient(1, "Ann <ann@example.com>", RecipientStatus::Pending),
+        recipient(2, "n@example.com", RecipientStatus::Pending),
+        recipient(3, "bob@example.com", RecipientStatus::Failed),
+    ];
+
+    let rejected = |error: &str| find_rejected_recipient(error, &recipients).map(|r| r.id);
+
+    assert_eq!(
+        rejected("permanent error (550): 5.1.1 <Ann@example.com>: Recipient address rejected"),
+        Some(1)
+    );
+    assert_eq!(
+        rejected("permanent error (550): 5.1.1 <n@example.com>: User unknown"),
+        Some(2)
+    );
+    assert_eq!(
+        rejected("permanent error (550): <bob@example.com> unknown"),
+        None
+    );
+    assert_eq!(rejected("Connection refused"), None);
+}
+
+fn is_failed(recipient: &MailRecipient) -> bool {
+    recipient.status == RecipientStatus::Failed.as_str()
+}
+
+/// The number of recipients of a mail at every domain it is delivered to.
+fn count_recipient_domains(mail: &Mail, recipients: &[MailRecipient]) -> HashMap<String, u32> {
+    // Mails queued before recipients were stored separately only have the `recipient` column.
+    if recipients.is_empty() {
+        return HashMap::from([(get_recipient_domain(&mail.recipient), 1)]);
+    }
+
+    let mut domains: HashMap<String, u32> = HashMap::new();
+    for recipient in recipients.iter().filter(|recipient| !is_failed(recipient)) {
+        *domains
+            .entry(get_recipient_domain(&recipient.address))
+            .or_default() += 1;
+    }
+
+    domains
+}
+
+/// Hand mails that hit a rate limit back to the queue, without counting it as an attempt.
+fn release_rate_limited_mails(pool: &ConnectionPool, rate_limited_mails: Vec<(Mail, StdDuration)>) {
+    let mut conn = match pool.get() {
+        Ok(conn) => conn,
+        // The leases expire, so another run will pick the mails up again.
         Err(_) => return,
     };
 
+    for (mail, wait) in rate_limited_mails {
+        let new_status = if mail.send_attempts > 0 {
+            MailStatus::Retrying
+        } else {
+            MailStatus::Queued
+        };
+
+        if let Err(err) = diesel::update(mails.filter(id.eq(mail.id)))
+            .set((
+                status.eq(new_status.as_str()),
+                locked_until.eq(None::<SystemTime>),
+                next_attempt_at.eq(SystemTime::now() + wait),
+            ))
+            .execute(&mut conn)
+        {
+            tracing::error!("Failed to release rate limited mail {}: {}", mail.id, err);
+        }
+    }
+}
+
+/// Send a single claimed mail and record the outcome. This does blocking IO, so it should be
+/// run on the blocking thread pool.
+fn deliver_mail(
+    pool: &ConnectionPool,
+    transport: &dyn MailTransport,
+    dkim_keys: &DkimKeys,
+    mail: Mail,
+    recipients: Vec<MailRecipient>,
+) {
     let mut conn = match pool.get() {
         Ok(conn) => conn,
-        Err(_) => return,
+        Err(_) => {
+            // The lease on the mail expires, so another run will pick it up again.
+            tracing::error!("Failed to get a database connection for mail {}", mail.id);
+            return;
+        }
+    };
+
+    let attachments = match mail_attachments::table
+        .filter(mail_attachments::mail_id.eq(mail.id))
+        .load::<MailAttachment>(&mut conn)
+    {
+        Ok(attachments) => attachments,
+        Err(_) => {
+            tracing::error!("Failed to load attachments of mail {}", mail.id);
+            return;
+        }
     };
 
-    for mail in scheduled_mails {
-        match send_mail(mail.clone()).await {
-            Ok(_) => {
-                match diesel::update(mails.filter(id.eq(mail.id)))
-                    .set(sent_at.eq(SystemTime::now()))
-                    .execute(&mut conn)
-                {
-                    Ok(_) => tracing::info!("Sent mail {} to {}", mail.id, mail.recipient),
-                    Err(_) => tracing::error!("Failed to update mail {}", mail.id),
+    match send_mail(transport, dkim_keys, mail.clone(), attachments, &recipients) {
+        Ok(smtp_code) => {
+            match conn.transaction(|conn| {
+                diesel::update(mails.filter(id.eq(mail.id)))
+                    .set((
+                        sent_at.eq(SystemTime::now()),
+                        status.eq(MailStatus::Sent.as_str()),
+                        locked_until.eq(None::<SystemTime>),
+                    ))
+                    .execute(conn)?;
+
+                mark_recipients_sent(conn, mail.id, smtp_code)?;
+                record_event(conn, mail.id, MailEventType::Sent, smtp_code, None)
+            }) {
+                Ok(_) => tracing::info!("Sent mail {} to {}", mail.id, mail.recipient),
+                Err(_) => tracing::error!("Failed to update mail {}", mail.id),
+            }
+        }
+        Err(err) => {
+            let rejected_recipient = find_rejected_recipient(&err.message, &recipients);
+
+            // A recipient the server refused for good doesn't fail the whole mail, the next
+            // attempt leaves them out and delivers to the others.
+            let permanent = err.permanent
+                && !rejected_recipient.is_some_and(|rejected| {
+                    recipients
+                        .iter()
+                        .any(|recipient| recipient.id != rejected.id && !is_failed(recipient))
+                });
+
+            let attempts = mail.send_attempts + 1;
+            let expired = !permanent && attempts >= get_max_send_attempts();
+            let new_status = if permanent || expired {
+                MailStatus::Failed
+            } else {
+                MailStatus::Retrying
+            };
+            let retry_at = SystemTime::now() + get_retry_delay(attempts);
+            let error_message = err.to_string();
+
+            match conn.transaction(|conn| {
+                diesel::update(mails.filter(id.eq(mail.id)))
+                    .set((
+                        send_attempts.eq(attempts),
+                        status.eq(new_status.as_str()),
+                        locked_until.eq(None::<SystemTime>),
+                        next_attempt_at.eq(retry_at),
+                        last_error.eq(&error_message),
+                    ))
+                    .execute(conn)?;
+
+                if let Some(rejected_recipient) = rejected_recipient {
+                    let recipient_status = if err.permanent {
+                        RecipientStatus::Failed
+                    } else {
+                        RecipientStatus::Pending
+                    };
+                    update_recipient_status(
+                        conn,
+                        rejected_recipient.id,
+                        recipient_status,
+                        err.code,
+                        &error_message,
+                    )?;
                 }
+
+                record_event(
+                    conn,
+                    mail.id,
+                    MailEventType::AttemptFailed,
+                    err.code,
+                    Some(&err.message),
+                )?;
+
+                if permanent {
+                    record_event(
+                        conn,
+                        mail.id,
+                        MailEventType::Failed,
+                        err.code,
+                        Some("Permanent failure, not retrying"),
+                    )?;
+                } else if expired {
+                    record_event(
+                        conn,
+                        mail.id,
+                        MailEventType::Expired,
+                        None,
+                        Some(&format!("Gave up after {attempts} attempts")),
+                    )?;
+                }
+
+                Ok::<(), diesel::result::Error>(())
+            }) {
+                Ok(_) if permanent => tracing::error!(
+                    "Failed to send mail {}, permanent failure: {}",
+                    mail.id,
+                    error_message
+                ),
+                Ok(_) if expired => tracing::error!(
+                    "Failed to send mail {}, giving up after {} attempts: {}",
+                    mail.id,
+                    attempts,
+                    error_message
+                ),
+                Ok(_) => tracing::error!(
+                    "Failed to send mail {}, retrying at {}: {}",
+                    mail.id,
+                    meel_utils::time::system_time_to_iso_string(retry_at),
+                    error_message
+                ),
+                Err(_) => tracing::error!("Failed to update mail {}", mail.id),
             }
-            Err(err) => {
-                match diesel::update(mails.filter(id.eq(mail.id)))
-                    .set(send_attempts.eq(send_attempts + 1))
-                    .execute(&mut conn)
-                {
-                    Ok(_) => tracing::error!("Failed to send mail {}: {}", mail.id, err),
-                    Err(_) => tracing::error!("Failed to update mail {}", mail.id),
+        }
+    }
+}
+
+pub async fn send_mails(
+    pool: Arc<ConnectionPool>,
+    transport: Arc<dyn MailTransport>,
+    rate_limits: Arc<OutboundRateLimits>,
+    dkim_keys: Arc<DkimKeys>,
+) {
+    const DEFAULT_BATCH_SIZE: i64 = 100;
+    let batch_size = meel_utils::env::get_var(
+        "MEEL_SCHEDULER_BATCH_SIZE",
+        Some(&DEFAULT_BATCH_SIZE.to_string()),
+    )
+    .unwrap()
+    .parse::<i64>()
+    .unwrap_or(DEFAULT_BATCH_SIZE)
+    .max(1);
+
+    const DEFAULT_CONCURRENCY: usize = 4;
+    let concurrency = meel_utils::env::get_var(
+        "MEEL_SCHEDULER_CONCURRENCY",
+        Some(&DEFAULT_CONCURRENCY.to_string()),
+    )
+    .unwrap()
+    .parse::<usize>()
+    .unwrap_or(DEFAULT_CONCURRENCY)
+    .max(1);
+
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+
+    match pool.get().map(|mut conn| expire_exhausted_mails(&mut conn)) {
+        Ok(Ok(expired_ids)) if !expired_ids.is_empty() => tracing::warn!(
+            "Failed {} mails that used up their send attempts",
+            expired_ids.len()
+        ),
+        Ok(Ok(_)) => (),
+        Ok(Err(err)) => tracing::error!("Failed to expire mails: {}", err),
+        Err(_) => return,
+    }
+
+    // Keep claiming batches until there is nothing left that is due.
+    loop {
+        let (claimed_mails, mut recipients_by_mail) = {
+            let mut conn = match pool.get() {
+                Ok(conn) => conn,
+                Err(_) => return,
+            };
+
+            let claimed_mails = match claim_mails(&mut conn, batch_size) {
+                Ok(claimed_mails) => claimed_mails,
+                Err(err) => {
+                    tracing::error!("Failed to claim mails: {}", err);
+                    return;
                 }
+            };
+
+            if claimed_mails.is_empty() {
+                break;
+            }
+
+            let mail_ids: Vec<i32> = claimed_mails.iter().map(|mail| mail.id).collect();
+            match load_recipients(&mut conn, &mail_ids) {
+                Ok(recipients_by_mail) => (claimed_mails, recipients_by_mail),
+                Err(err) => {
+                    // The leases on the mails expire, so another run will pick them up again.
+                    tracing::error!("Failed to load recipients of claimed mails: {}", err);
+                    return;
+                }
+            }
+        };
+
+        let mut tasks = JoinSet::new();
+        let mut rate_limited_mails = vec![];
+
+        for mail in claimed_mails {
+            let recipients = recipients_by_mail.remove(&mail.id).unwrap_or_default();
+            let recipient_domains = count_recipient_domains(&mail, &recipients);
+
+            if let Err(wait) = rate_limits.try_acquire(&recipient_domains) {
+                rate_limited_mails.push((mail, wait));
+                continue;
             }
+
+            // Closing the semaphore never happens, so acquiring can't fail.
+            let permit = semaphore.clone().acquire_owned().await.unwrap();
+            let pool = pool.clone();
+            let transport = transport.clone();
+            let dkim_keys = dkim_keys.clone();
+
+            tasks.spawn_blocking(move || {
+                deliver_mail(&pool, transport.as_ref(), &dkim_keys, mail, recipients);
+                drop(permit);
+            });
         }
+
+        if !rate_limited_mails.is_empty() {
+            tracing::debug!(
+                "Rate limit reached, postponing {} mails",
+                rate_limited_mails.len()
+            );
+
+            let pool = pool.clone();
+            tasks.spawn_blocking(move || release_rate_limited_mails(&pool, rate_limited_mails));
+        }
+
+        while tasks.join_next().await.is_some() {}
     }
 
     remove_old_sent_emails(pool).await;
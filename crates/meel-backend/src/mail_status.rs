@@ -0,0 +1,73 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+
+/// Where a mail is in its lifecycle, stored in the `status` column of `mails`.
+///
+/// ```text
+/// queued ──> sending ──> sent
+///   │          │
+///   │          ├──> retrying ──> sending ...
+///   │          │
+///   │          └──> failed
+///   └──> cancelled
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailStatus {
+    /// Waiting for its scheduled time.
+    Queued,
+    /// Claimed by a scheduler run, which is delivering it right now.
+    Sending,
+    Sent,
+    /// A previous attempt failed, and the mail waits for its next attempt.
+    Retrying,
+    /// The mail was rejected permanently, or ran out of attempts. It won't be retried.
+    Failed,
+    Cancelled,
+}
+
+impl MailStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailStatus::Queued => "queued",
+            MailStatus::Sending => "sending",
+            MailStatus::Sent => "sent",
+            MailStatus::Retrying => "retrying",
+            MailStatus::Failed => "failed",
+            MailStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl FromStr for MailStatus {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "queued" => Ok(MailStatus::Queued),
+            "sending" => Ok(MailStatus::Sending),
+            "sent" => Ok(MailStatus::Sent),
+            "retrying" => Ok(MailStatus::Retrying),
+            "failed" => Ok(MailStatus::Failed),
+            "cancelled" => Ok(MailStatus::Cancelled),
+            _ => Err(format!("Unknown mail status `{value}`")),
+        }
+    }
+}
+
+#[test]
+fn test_mail_status_round_trip() {
+    for status in [
+        MailStatus::Queued,
+        MailStatus::Sending,
+        MailStatus::Sent,
+        MailStatus::Retrying,
+        MailStatus::Failed,
+        MailStatus::Cancelled,
+    ] {
+        assert_eq!(status.as_str().parse::<MailStatus>(), Ok(status));
+    }
+
+    assert!("unknown".parse::<MailStatus>().is_err());
+}
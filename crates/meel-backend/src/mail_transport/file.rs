@@ -0,0 +1,39 @@
+use std::fs;
+
+use lettre::{FileTransport, Message, Transport};
+
+use crate::mail_transport::{MailTransport, MailTransportError};
+
+/// Writes every mail as an `.eml` file instead of delivering it. Useful for staging
+/// environments, where mail should never reach real recipients.
+pub struct FileMailTransport {
+    transport: FileTransport,
+}
+
+impl FileMailTransport {
+    pub fn from_env() -> Result<Self, String> {
+        let default_directory = format!(
+            "{}/outbox",
+            meel_utils::env::get_var("MEEL_DATA_DIRECTORY", Some("./data")).unwrap()
+        );
+        let directory =
+            meel_utils::env::get_var("MEEL_MAIL_FILE_DIRECTORY", Some(&default_directory)).unwrap();
+
+        if fs::create_dir_all(&directory).is_err() {
+            return Err(format!("Failed to create mail directory {directory}"));
+        }
+
+        Ok(Self {
+            transport: FileTransport::new(directory),
+        })
+    }
+}
+
+impl MailTransport for FileMailTransport {
+    fn send(&self, message: &Message) -> Result<Option<u16>, MailTransportError> {
+        match self.transport.send(message) {
+            Ok(_) => Ok(None),
+            Err(err) => Err(MailTransportError::transient(err.to_string())),
+        }
+    }
+}
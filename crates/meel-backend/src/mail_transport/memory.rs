@@ -0,0 +1,55 @@
+use std::sync::Mutex;
+
+use lettre::Message;
+
+use crate::mail_transport::{MailTransport, MailTransportError};
+
+/// Keeps every mail in memory, so tests can inspect what would have been sent.
+#[derive(Default)]
+pub struct MemoryMailTransport {
+    messages: Mutex<Vec<Message>>,
+}
+
+impl MemoryMailTransport {
+    #[cfg(test)]
+    pub fn messages(&self) -> Vec<Message> {
+        match self.messages.lock() {
+            Ok(messages) => messages.clone(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+impl MailTransport for MemoryMailTransport {
+    fn send(&self, message: &Message) -> Result<Option<u16>, MailTransportError> {
+        match self.messages.lock() {
+            Ok(mut messages) => {
+                messages.push(message.clone());
+                Ok(None)
+            }
+            Err(_) => Err(MailTransportError::transient(
+                "Failed to lock in-memory mailbox".to_string(),
+            )),
+        }
+    }
+}
+
+#[test]
+fn test_memory_mail_transport() {
+    let transport = MemoryMailTransport::default();
+    let message = Message::builder()
+        .from("sender@example.com".parse().unwrap())
+        .to("recipient@example.com".parse().unwrap())
+        .subject("Hello")
+        .body("World".to_string())
+        .unwrap();
+
+    transport.send(&message).unwrap();
+
+    let messages = transport.messages();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(
+        messages[0].envelope().to()[0].to_string(),
+        "recipient@example.com"
+    );
+}
@@ -0,0 +1,70 @@
+use std::fmt::{Display, Formatter};
+
+use lettre::Message;
+
+mod file;
+mod memory;
+mod sendmail;
+mod smtp;
+
+pub use file::FileMailTransport;
+pub use memory::MemoryMailTransport;
+pub use sendmail::SendmailMailTransport;
+pub use smtp::SmtpMailTransport;
+
+#[derive(Debug)]
+pub struct MailTransportError {
+    /// The SMTP reply code, if the server rejected the mail.
+    pub code: Option<u16>,
+    pub message: String,
+    /// Whether retrying can't possibly succeed, e.g. a 5xx reply or an unparsable address.
+    pub permanent: bool,
+}
+
+impl MailTransportError {
+    /// A failure that may go away by itself, such as a network error.
+    pub fn transient(message: String) -> Self {
+        Self {
+            code: None,
+            message,
+            permanent: false,
+        }
+    }
+
+    /// A failure that will happen again on every attempt.
+    pub fn permanent(message: String) -> Self {
+        Self {
+            code: None,
+            message,
+            permanent: true,
+        }
+    }
+}
+
+impl Display for MailTransportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{} ({})", self.message, code),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// A backend that delivers fully built messages.
+pub trait MailTransport: Send + Sync {
+    /// Deliver the message, returning the SMTP reply code it was accepted with, if there is one.
+    fn send(&self, message: &Message) -> Result<Option<u16>, MailTransportError>;
+}
+
+/// Create the transport selected by `MEEL_MAIL_TRANSPORT`. Defaults to SMTP.
+pub fn from_env() -> Result<Box<dyn MailTransport>, String> {
+    let transport = meel_utils::env::get_var("MEEL_MAIL_TRANSPORT", Some("smtp")).unwrap();
+
+    match transport.to_lowercase().as_str() {
+        "smtp" => Ok(Box::new(SmtpMailTransport::from_env()?)),
+        "sendmail" => Ok(Box::new(SendmailMailTransport::from_env())),
+        "file" => Ok(Box::new(FileMailTransport::from_env()?)),
+        "memory" => Ok(Box::new(MemoryMailTransport::default())),
+        _ => Err(format!("Unknown mail transport `{transport}`")),
+    }
+}
@@ -0,0 +1,28 @@
+use lettre::{Message, SendmailTransport, Transport};
+
+use crate::mail_transport::{MailTransport, MailTransportError};
+
+/// Hands mail to a local `sendmail` compatible binary.
+pub struct SendmailMailTransport {
+    transport: SendmailTransport,
+}
+
+impl SendmailMailTransport {
+    pub fn from_env() -> Self {
+        let transport = match meel_utils::env::get_var("MEEL_SENDMAIL_COMMAND", None) {
+            Some(command) => SendmailTransport::new_with_command(command),
+            None => SendmailTransport::new(),
+        };
+
+        Self { transport }
+    }
+}
+
+impl MailTransport for SendmailMailTransport {
+    fn send(&self, message: &Message) -> Result<Option<u16>, MailTransportError> {
+        match self.transport.send(message) {
+            Ok(_) => Ok(None),
+            Err(err) => Err(MailTransportError::transient(err.to_string())),
+        }
+    }
+}
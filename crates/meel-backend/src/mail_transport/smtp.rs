@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::PoolConfig;
+use lettre::{Message, SmtpTransport, Transport};
+
+use crate::mail_transport::{MailTransport, MailTransportError};
+
+/// Delivers mail to an SMTP relay, or to a local unauthenticated server such as MailHog.
+///
+/// The underlying transport keeps a pool of open connections, so this should be created once and
+/// shared, rather than rebuilt for every mail.
+pub struct SmtpMailTransport {
+    transport: SmtpTransport,
+}
+
+impl SmtpMailTransport {
+    pub fn from_env() -> Result<Self, String> {
+        let pool_config = get_pool_config();
+        let smtp_relay = meel_utils::env::get_var("MEEL_SMTP_RELAY", None);
+
+        let transport = if let Some(smtp_relay) = smtp_relay {
+            let smtp_username = match meel_utils::env::get_var("MEEL_SMTP_USERNAME", None) {
+                Some(username) => username,
+                None => return Err("MEEL_SMTP_USERNAME must be set".to_string()),
+            };
+
+            let smtp_password = match meel_utils::env::get_var("MEEL_SMTP_PASSWORD", None) {
+                Some(password) => password,
+                None => return Err("MEEL_SMTP_PASSWORD must be set".to_string()),
+            };
+
+            let creds = Credentials::new(smtp_username, smtp_password);
+
+            match SmtpTransport::relay(&smtp_relay) {
+                Ok(mailer) => mailer.credentials(creds).pool_config(pool_config).build(),
+                Err(_) => return Err("Failed to build mailer".to_string()),
+            }
+        } else {
+            let transport_domain =
+                meel_utils::env::get_var("MEEL_TRANSPORT_DOMAIN", Some("mailhog"));
+            let transport_port = meel_utils::env::get_var("MEEL_TRANSPORT_PORT", Some("1025"));
+
+            // We can use unwrap safely here, as we have a fallback set above.
+            SmtpTransport::builder_dangerous(transport_domain.unwrap())
+                .port(transport_port.unwrap().parse().unwrap_or(1025))
+                .pool_config(pool_config)
+                .build()
+        };
+
+        Ok(Self { transport })
+    }
+}
+
+fn get_pool_config() -> PoolConfig {
+    const DEFAULT_POOL_MAX_SIZE: u32 = 10;
+    let max_size = meel_utils::env::get_var(
+        "MEEL_SMTP_POOL_MAX_SIZE",
+        Some(&DEFAULT_POOL_MAX_SIZE.to_string()),
+    )
+    .unwrap()
+    .parse::<u32>()
+    .unwrap_or(DEFAULT_POOL_MAX_SIZE);
+
+    const DEFAULT_POOL_IDLE_TIMEOUT: u64 = 60;
+    let idle_timeout = meel_utils::env::get_var(
+        "MEEL_SMTP_POOL_IDLE_TIMEOUT",
+        Some(&DEFAULT_POOL_IDLE_TIMEOUT.to_string()),
+    )
+    .unwrap()
+    .parse::<u64>()
+    .unwrap_or(DEFAULT_POOL_IDLE_TIMEOUT);
+
+    PoolConfig::new()
+        .max_size(max_size)
+        .idle_timeout(Duration::from_secs(idle_timeout))
+}
+
+impl MailTransport for SmtpMailTransport {
+    fn send(&self, message: &Message) -> Result<Option<u16>, MailTransportError> {
+        match self.transport.send(message) {
+            Ok(response) => Ok(Some(u16::from(response.code()))),
+            // Only a 5xx reply is permanent, anything else (4xx, network, TLS, timeouts) might
+            // succeed on a later attempt.
+            Err(err) => Err(MailTransportError {
+                code: err.status().map(u16::from),
+                message: err.to_string(),
+                permanent: err.is_permanent(),
+            }),
+        }
+    }
+}
@@ -1,14 +1,29 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use dotenvy::dotenv;
 use tokio::net::TcpListener;
 
 use crate::database::ConnectionPool;
+use crate::dkim::DkimKeys;
+use crate::mail_transport::MailTransport;
+use crate::rate_limiter::OutboundRateLimits;
 
+mod allowed_senders;
+mod attachments;
+mod auth;
 mod database;
+mod dkim;
+mod mail_events;
+mod mail_headers;
+mod mail_recipients;
 mod mail_scheduler;
+mod mail_status;
+mod mail_transport;
+mod rate_limiter;
 mod routes;
 mod server;
+mod unsubscribe;
 
 async fn start_web_server(shared_pool: Arc<ConnectionPool>) {
     let address = meel_utils::env::get_var("MEEL_HOST", Some("0.0.0.0:8080")).unwrap();
@@ -18,17 +33,45 @@ async fn start_web_server(shared_pool: Arc<ConnectionPool>) {
 
     tracing::info!("Webserver listening on {}", address);
     let server = server::create(shared_pool).await;
-    axum::serve(listener, server)
-        .await
-        .expect("Failed to start server");
+    // The connection info is used to rate limit requests by IP address.
+    axum::serve(
+        listener,
+        server.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .expect("Failed to start server");
 }
 
 async fn start_mail_scheduler(shared_pool: Arc<ConnectionPool>) {
     tracing::info!("Starting mail scheduler");
 
+    // The transport is created once and shared between runs, so pooled SMTP connections are
+    // reused instead of opening a new session for every mail.
+    let transport: Arc<dyn MailTransport> =
+        Arc::from(mail_transport::from_env().expect("Failed to create mail transport"));
+
+    // Rate limit windows have to outlive a single run, so the limits are shared as well.
+    let rate_limits = Arc::new(OutboundRateLimits::from_env());
+
+    // Parsing the keys is relatively expensive, so they are only loaded once.
+    let dkim_keys = Arc::new(DkimKeys::load());
+
+    let mut running: Option<tokio::task::JoinHandle<()>> = None;
+
     loop {
-        // Move this to a new thread, so it doesn't block loop interval
-        tokio::spawn(mail_scheduler::send_mails(shared_pool.clone()));
+        // Don't start a new run while the previous one is still sending, the mails it claimed
+        // are locked anyway, and piling up runs only exhausts the connection pool.
+        if running.as_ref().is_some_and(|handle| !handle.is_finished()) {
+            tracing::debug!("Previous mail scheduler run is still in progress, skipping");
+        } else {
+            // Move this to a new thread, so it doesn't block loop interval
+            running = Some(tokio::spawn(mail_scheduler::send_mails(
+                shared_pool.clone(),
+                transport.clone(),
+                rate_limits.clone(),
+                dkim_keys.clone(),
+            )));
+        }
 
         const DEFAULT_SLEEP_INTERVAL: u64 = 15;
         let sleep_interval = meel_utils::env::get_var(
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Counts events per key in fixed windows, and rejects them once a key exceeds its limit.
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create a limiter from an environment variable holding the number of events allowed per
+    /// minute. Returns `None` when the variable is unset or `0`, meaning unlimited.
+    pub fn per_minute_from_env(name: &str) -> Option<Self> {
+        meel_utils::env::get_var(name, None)
+            .and_then(|value| value.trim().parse::<u32>().ok())
+            .filter(|limit| *limit > 0)
+            .map(|limit| Self::new(limit, Duration::from_secs(60)))
+    }
+
+    /// Record `count` events for the key. When that would exceed the limit nothing is recorded,
+    /// and the time until the current window ends is returned instead. A `count` above the whole
+    /// limit is allowed in an empty window, and uses it up, so it can't be rejected forever.
+    pub fn try_acquire(&self, key: &str, count: u32) -> Result<(), Duration> {
+        self.try_acquire_at(key, count, Instant::now())
+    }
+
+    /// Check whether `count` events would be allowed for the key, without recording them.
+    pub fn check(&self, key: &str, count: u32) -> Result<(), Duration> {
+        let now = Instant::now();
+        let windows = self.windows.lock().unwrap();
+
+        match windows.get(key) {
+            Some((started, used))
+                if now.duration_since(*started) < self.window
+                    && *used > 0
+                    && used.saturating_add(count) > self.limit =>
+            {
+                Err(self.window - now.duration_since(*started))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn try_acquire_at(&self, key: &str, count: u32, now: Instant) -> Result<(), Duration> {
+        let mut windows = self.windows.lock().unwrap();
+
+        // Forget keys that haven't been seen for a while, so the map doesn't grow forever.
+        if windows.len() > 10_000 {
+            windows.retain(|_, (started, _)| now.duration_since(*started) < self.window);
+        }
+
+        let (started, used) = windows.entry(key.to_string()).or_insert((now, 0));
+
+        if now.duration_since(*started) >= self.window {
+            *started = now;
+            *used = 0;
+        }
+
+        if *used > 0 && used.saturating_add(count) > self.limit {
+            return Err(self.window - now.duration_since(*started));
+        }
+
+        *used = used.saturating_add(count);
+        Ok(())
+    }
+}
+
+/// Ingestion limit on `/mails/send`, in mails per minute per API key.
+pub struct SendRateLimit(Option<RateLimiter>);
+
+impl SendRateLimit {
+    pub fn from_env() -> Self {
+        Self(RateLimiter::per_minute_from_env("MEEL_SEND_RATE_LIMIT"))
+    }
+
+    /// The number of mails allowed per minute, `None` when unlimited.
+    pub fn limit(&self) -> Option<u32> {
+        self.0.as_ref().map(|limiter| limiter.limit)
+    }
+
+    pub fn try_acquire(&self, key: &str, count: u32) -> Result<(), Duration> {
+        match &self.0 {
+            Some(limiter) => limiter.try_acquire(key, count),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Outbound throttling for the scheduler, so we stay under the quota of the mail relay.
+pub struct OutboundRateLimits {
+    global: Option<RateLimiter>,
+    default_domain: Option<RateLimiter>,
+    domains: HashMap<String, RateLimiter>,
+    /// Held while checking and recording a mail, so concurrent sends can't record it in the
+    /// global limit and then be rejected by a domain that filled up in between.
+    acquiring: Mutex<()>,
+}
+
+/// Parse per domain limits formatted as `gmail.com=100,outlook.com=50`.
+fn parse_domain_limits(value: &str) -> HashMap<String, u32> {
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let (domain, limit) = entry.split_once('=')?;
+            let limit = limit.trim().parse::<u32>().ok()?;
+            Some((domain.trim().to_lowercase(), limit))
+        })
+        .filter(|(domain, _)| !domain.is_empty())
+        .collect()
+}
+
+impl OutboundRateLimits {
+    pub fn from_env() -> Self {
+        let domains = meel_utils::env::get_var("MEEL_OUTBOUND_DOMAIN_RATE_LIMITS", None)
+            .map(|value| parse_domain_limits(&value))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(_, limit)| *limit > 0)
+            .map(|(domain, limit)| (domain, RateLimiter::new(limit, Duration::from_secs(60))))
+            .collect();
+
+        Self {
+            global: RateLimiter::per_minute_from_env("MEEL_OUTBOUND_RATE_LIMIT"),
+            default_domain: RateLimiter::per_minute_from_env("MEEL_OUTBOUND_DOMAIN_RATE_LIMIT"),
+            domains,
+            acquiring: Mutex::new(()),
+        }
+    }
+
+    /// Record a mail to the recipient domains, counting every recipient at a domain, or return how
+    /// long to wait when any of the domains or the global limit has been reached. Like
+    /// [`RateLimiter::try_acquire`], a mail with more recipients than a limit allows is sent once
+    /// that limit has an empty window.
+    pub fn try_acquire(&self, recipient_domains: &HashMap<String, u32>) -> Result<(), Duration> {
+        let _acquiring = self.acquiring.lock().unwrap();
+        let domain_limiters: Vec<(String, u32, &RateLimiter)> = recipient_domains
+            .iter()
+            .filter_map(|(domain, count)| {
+                let domain = domain.to_lowercase();
+                let limiter = self.domains.get(&domain).or(self.default_domain.as_ref())?;
+                Some((domain, *count, limiter))
+            })
+            .collect();
+
+        // Check the domains first, so a rejected mail doesn't use up the global limit.
+        for (domain, count, limiter) in &domain_limiters {
+            limiter.check(domain, *count)?;
+        }
+
+        if let Some(global) = &self.global {
+            global.try_acquire("global", recipient_domains.values().sum())?;
+        }
+
+        for (domain, count, limiter) in &domain_limiters {
+            limiter.try_acquire(domain, *count)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_rate_limiter() {
+    let limiter = RateLimiter::new(3, Duration::from_secs(60));
+    let now = Instant::now();
+
+    assert!(limiter.try_acquire_at("a", 2, now).is_ok());
+    assert!(limiter.try_acquire_at("a", 1, now).is_ok());
+    assert_eq!(
+        limiter.try_acquire_at("a", 1, now + Duration::from_secs(20)),
+        Err(Duration::from_secs(40))
+    );
+
+    // Other keys have their own window.
+    assert!(limiter.try_acquire_at("b", 3, now).is_ok());
+
+    // A new window starts once the previous one has passed.
+    assert!(limiter
+        .try_acquire_at("a", 3, now + Duration::from_secs(60))
+        .is_ok());
+
+    // More events than the whole limit are allowed in an empty window, which they use up.
+    assert!(limiter.try_acquire_at("c", 5, now).is_ok());
+    assert_eq!(
+        limiter.try_acquire_at("c", 1, now + Duration::from_secs(30)),
+        Err(Duration::from_secs(30))
+    );
+    assert!(limiter
+        .try_acquire_at("c", 5, now + Duration::from_secs(60))
+        .is_ok());
+}
+
+#[test]
+fn test_parse_domain_limits() {
+    let limits = parse_domain_limits("gmail.com=100, Outlook.com = 50,invalid,=3");
+
+    assert_eq!(limits.len(), 2);
+    assert_eq!(limits.get("gmail.com"), Some(&100));
+    assert_eq!(limits.get("outlook.com"), Some(&50));
+}
+
+#[test]
+fn test_outbound_rate_limits() {
+    let rate_limits = OutboundRateLimits {
+        global: Some(RateLimiter::new(5, Duration::from_secs(60))),
+        default_domain: None,
+        domains: HashMap::from([(
+            "gmail.com".to_string(),
+            RateLimiter::new(3, Duration::from_secs(60)),
+        )]),
+        acquiring: Mutex::new(()),
+    };
+
+    let recipient_domains =
+        HashMap::from([("gmail.com".to_string(), 2), ("example.com".to_string(), 1)]);
+    assert!(rate_limits.try_acquire(&recipient_domains).is_ok());
+
+    // Every recipient counts, a mail to two more gmail.com addresses is over the domain limit.
+    assert!(rate_limits
+        .try_acquire(&HashMap::from([("Gmail.com".to_string(), 2)]))
+        .is_err());
+    assert!(rate_limits
+        .try_acquire(&HashMap::from([("example.com".to_string(), 2)]))
+        .is_ok());
+    assert!(rate_limits
+        .try_acquire(&HashMap::from([("example.com".to_string(), 1)]))
+        .is_err());
+}
+
+#[test]
+fn test_outbound_rate_limits_oversized_mail() {
+    let rate_limits = OutboundRateLimits {
+        global: Some(RateLimiter::new(5, Duration::from_secs(60))),
+        default_domain: None,
+        domains: HashMap::from([(
+            "gmail.com".to_string(),
+            RateLimiter::new(3, Duration::from_secs(60)),
+        )]),
+        acquiring: Mutex::new(()),
+    };
+
+    // More recipients than the global limit allows still go out while the window is empty.
+    assert!(rate_limits
+        .try_acquire(&HashMap::from([("example.com".to_string(), 7)]))
+        .is_ok());
+    assert!(rate_limits
+        .try_acquire(&HashMap::from([("example.com".to_string(), 1)]))
+        .is_err());
+
+    // The same goes for a domain limit.
+    let rate_limits = OutboundRateLimits {
+        global: None,
+        ..rate_limits
+    };
+    assert!(rate_limits
+        .try_acquire(&HashMap::from([("gmail.com".to_string(), 4)]))
+        .is_ok());
+    assert!(rate_limits
+        .try_acquire(&HashMap::from([("gmail.com".to_string(), 1)]))
+        .is_err());
+}
@@ -0,0 +1,189 @@
+use crate::auth::{generate_api_key, hash_api_key, ApiKeyScope};
+use crate::database;
+use crate::database::models::{ApiKey, NewApiKey};
+use crate::routes::extract::{Json, Path};
+use crate::routes::{database_error, get_connection};
+use axum::http::StatusCode;
+use axum::Extension;
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
+use meel_utils::api_error::{ApiError, ApiErrorCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+#[derive(Serialize)]
+pub struct ApiKeyResponse {
+    id: i32,
+    name: String,
+    key_prefix: String,
+    scopes: Vec<String>,
+    allowed_senders: Vec<String>,
+    created_at: String,
+    last_used_at: Option<String>,
+    revoked_at: Option<String>,
+}
+
+impl ApiKeyResponse {
+    fn new(api_key: ApiKey) -> Self {
+        Self {
+            id: api_key.id,
+            name: api_key.name,
+            key_prefix: api_key.key_prefix,
+            scopes: api_key.scopes,
+            allowed_senders: api_key.allowed_senders,
+            created_at: meel_utils::time::system_time_to_iso_string(api_key.created_at),
+            last_used_at: api_key
+                .last_used_at
+                .map(meel_utils::time::system_time_to_iso_string),
+            revoked_at: api_key
+                .revoked_at
+                .map(meel_utils::time::system_time_to_iso_string),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CreateApiKeyResponse {
+    #[serde(flatten)]
+    api_key: ApiKeyResponse,
+    /// The full key, this is the only time it is returned.
+    key: String,
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Addresses (`noreply@example.com`) or domains (`example.com`) this key may send as.
+    pub allowed_senders: Option<Vec<String>>,
+}
+
+pub async fn get_api_keys(
+    pool: Extension<Arc<database::ConnectionPool>>,
+) -> Result<Json<Vec<ApiKeyResponse>>, ApiError> {
+    use crate::database::schema::api_keys;
+
+    let mut conn = get_connection(&pool)?;
+
+    let found_api_keys = api_keys::table
+        .order(api_keys::id.asc())
+        .select(ApiKey::as_select())
+        .load(&mut conn)
+        .map_err(|err| database_error("Failed to load API keys", err))?;
+
+    Ok(Json(
+        found_api_keys
+            .into_iter()
+            .map(ApiKeyResponse::new)
+            .collect(),
+    ))
+}
+
+pub async fn create_api_key(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Json(request): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreateApiKeyResponse>), ApiError> {
+    use crate::database::schema::api_keys;
+
+    if request.name.trim().is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            ApiErrorCode::ValidationError,
+            "Missing or invalid `name`".to_string(),
+            HashMap::new(),
+        ));
+    }
+
+    if request.scopes.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            ApiErrorCode::ValidationError,
+            "An API key needs at least one scope".to_string(),
+            HashMap::new(),
+        ));
+    }
+
+    let scopes = request
+        .scopes
+        .iter()
+        .map(|scope| scope.parse::<ApiKeyScope>().map(|scope| scope.to_string()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| {
+            ApiError::new(
+                StatusCode::BAD_REQUEST,
+                ApiErrorCode::ValidationError,
+                err,
+                HashMap::new(),
+            )
+        })?;
+
+    let allowed_senders: Vec<String> = request
+        .allowed_senders
+        .unwrap_or_default()
+        .iter()
+        .map(|sender| sender.trim().to_lowercase())
+        .filter(|sender| !sender.is_empty())
+        .collect();
+
+    let (key, key_prefix) = generate_api_key().map_err(|err| {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ApiErrorCode::Unknown,
+            "Failed to generate API key: ".to_string() + &err,
+            HashMap::new(),
+        )
+    })?;
+
+    let mut conn = get_connection(&pool)?;
+
+    let api_key = diesel::insert_into(api_keys::table)
+        .values(NewApiKey {
+            name: request.name.trim(),
+            key_prefix: &key_prefix,
+            key_hash: &hash_api_key(&key),
+            scopes: &scopes,
+            allowed_senders: &allowed_senders,
+        })
+        .returning(ApiKey::as_returning())
+        .get_result(&mut conn)
+        .map_err(|err| database_error("Failed to create API key", err))?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateApiKeyResponse {
+            api_key: ApiKeyResponse::new(api_key),
+            key,
+        }),
+    ))
+}
+
+/// Revoke an API key. Revoked keys are kept, so it stays visible which key was used when.
+pub async fn revoke_api_key(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path(api_key_id): Path<i32>,
+) -> Result<StatusCode, ApiError> {
+    use crate::database::schema::api_keys;
+
+    let mut conn = get_connection(&pool)?;
+
+    let revoked = diesel::update(
+        api_keys::table
+            .find(api_key_id)
+            .filter(api_keys::revoked_at.is_null()),
+    )
+    .set(api_keys::revoked_at.eq(SystemTime::now()))
+    .execute(&mut conn)
+    .map_err(|err| database_error("Failed to revoke API key", err))?;
+
+    if revoked == 0 {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            ApiErrorCode::NotFound,
+            format!("API key {api_key_id} not found"),
+            HashMap::new(),
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,629 @@
+use crate::auth::AuthenticatedKey;
+use crate::database::models::{
+    MailingList, MailingListSubscriber, NewMailingList, NewMailingListSubscriber, UpdateMailingList,
+};
+use crate::rate_limiter::SendRateLimit;
+use crate::routes::extract::{Json, Path, Query};
+use crate::routes::mails::{
+    acquire_send_rate_limit, decode_attachments, insert_mail_with_shared_attachments, prepare_mail,
+    store_shared_attachments, AttachmentRequest, PreparedMail, SendMailRequest, SendMailResponse,
+};
+use crate::routes::{database_error, get_connection};
+use crate::{attachments, database, unsubscribe};
+use axum::extract::ConnectInfo;
+use axum::http::StatusCode;
+use axum::Extension;
+use diesel::result::DatabaseErrorKind;
+use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
+use lettre::message::Mailbox;
+use lettre::Address;
+use meel_templating::locales;
+use meel_templating::templating::TemplateDataMap;
+use meel_utils::api_error::{ApiError, ApiErrorCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+#[derive(Serialize)]
+pub struct MailingListResponse {
+    id: i32,
+    name: String,
+    description: String,
+    created_at: Option<String>,
+    updated_at: Option<String>,
+}
+
+impl MailingListResponse {
+    fn new(mailing_list: MailingList) -> Self {
+        Self {
+            id: mailing_list.id,
+            name: mailing_list.name,
+            description: mailing_list.description,
+            created_at: mailing_list
+                .created_at
+                .map(meel_utils::time::system_time_to_iso_string),
+            updated_at: mailing_list
+                .updated_at
+                .map(meel_utils::time::system_time_to_iso_string),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SubscriberResponse {
+    id: i32,
+    email: String,
+    name: String,
+    locale: Option<String>,
+    created_at: Option<String>,
+}
+
+impl SubscriberResponse {
+    fn new(subscriber: MailingListSubscriber) -> Self {
+        Self {
+            id: subscriber.id,
+            email: subscriber.email,
+            name: subscriber.name,
+            locale: subscriber.locale,
+            created_at: subscriber
+                .created_at
+                .map(meel_utils::time::system_time_to_iso_string),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SubscribersPageResponse {
+    subscribers: Vec<SubscriberResponse>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+#[derive(Deserialize)]
+pub struct CreateMailingListRequest {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMailingListRequest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AddSubscriberRequest {
+    pub email: String,
+    pub name: Option<String>,
+    pub locale: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SendMailingListMailRequest {
+    #[serde(default)]
+    pub sender: String,
+    #[serde(default)]
+    pub subject: String,
+    pub template: String,
+    pub priority: i32,
+    pub data: TemplateDataMap,
+    pub allow_html: Option<bool>,
+    pub minify_html: Option<bool>,
+    pub schedule_at: Option<String>,
+    pub reply_to: Option<String>,
+    pub attachments: Option<Vec<AttachmentRequest>>,
+    pub headers: Option<HashMap<String, String>>,
+    pub list_unsubscribe: Option<Vec<String>>,
+    pub list_unsubscribe_one_click: Option<bool>,
+    pub tags: Option<Vec<String>>,
+    pub metadata: Option<HashMap<String, String>>,
+    /// The locale for subscribers that don't have one of their own.
+    pub locale: Option<String>,
+}
+
+impl SendMailingListMailRequest {
+    /// Create the mail request for a single subscriber, with the subscriber's details merged into
+    /// the template data.
+    fn for_subscriber(
+        &self,
+        mailing_list_id: i32,
+        subscriber: &MailingListSubscriber,
+    ) -> SendMailRequest {
+        let mut data = self.data.clone();
+        data.insert(
+            "email".to_string(),
+            serde_json::Value::String(subscriber.email.clone()),
+        );
+        data.insert(
+            "name".to_string(),
+            serde_json::Value::String(subscriber.name.clone()),
+        );
+
+        let mut list_unsubscribe = self.list_unsubscribe.clone();
+        let mut list_unsubscribe_one_click = self.list_unsubscribe_one_click;
+
+        if let Some(unsubscribe_url) =
+            unsubscribe::get_unsubscribe_url(Some(mailing_list_id), &subscriber.email)
+        {
+            // Unless the caller handles unsubscribing, point the list headers at our own link.
+            // One-click unsubscribe needs an https URL, so it is only enabled for those.
+            if list_unsubscribe.is_none() {
+                list_unsubscribe_one_click = Some(unsubscribe_url.starts_with("https://"));
+                list_unsubscribe = Some(vec![unsubscribe_url.clone()]);
+            }
+
+            data.insert(
+                "unsubscribe_url".to_string(),
+                serde_json::Value::String(unsubscribe_url),
+            );
+        }
+
+        SendMailRequest {
+            recipient: format_recipient(subscriber),
+            to: None,
+            cc: None,
+            bcc: None,
+            sender: self.sender.clone(),
+            subject: self.subject.clone(),
+            template: self.template.clone(),
+            priority: self.priority,
+            data,
+            allow_html: self.allow_html,
+            minify_html: self.minify_html,
+            schedule_at: self.schedule_at.clone(),
+            reply_to: self.reply_to.clone(),
+            attachments: None,
+            headers: self.headers.clone(),
+            list_unsubscribe,
+            list_unsubscribe_one_click,
+            tags: self.tags.clone(),
+            metadata: self.metadata.clone(),
+            locale: subscriber.locale.clone().or_else(|| self.locale.clone()),
+        }
+    }
+}
+
+/// Format the subscriber as `Name <email>`, falling back to the plain email address when the
+/// subscriber has no name.
+fn format_recipient(subscriber: &MailingListSubscriber) -> String {
+    if subscriber.name.trim().is_empty() {
+        return subscriber.email.clone();
+    }
+
+    match subscriber.email.parse::<Address>() {
+        Ok(address) => Mailbox::new(Some(subscriber.name.clone()), address).to_string(),
+        Err(_) => subscriber.email.clone(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PaginationQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+fn mailing_list_not_found(mailing_list_id: i32) -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        ApiErrorCode::NotFound,
+        format!("Mailing list {mailing_list_id} not found"),
+        HashMap::new(),
+    )
+}
+
+fn validate_name(name: &str) -> Result<(), ApiError> {
+    if name.trim().is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            ApiErrorCode::ValidationError,
+            "Missing or invalid `name`".to_string(),
+            HashMap::new(),
+        ));
+    }
+
+    Ok(())
+}
+
+pub async fn get_mailing_lists(
+    pool: Extension<Arc<database::ConnectionPool>>,
+) -> Result<Json<Vec<MailingListResponse>>, ApiError> {
+    use crate::database::schema::mailing_lists;
+
+    let mut conn = get_connection(&pool)?;
+
+    match mailing_lists::table
+        .order(mailing_lists::id.asc())
+        .load::<MailingList>(&mut conn)
+    {
+        Ok(lists) => Ok(Json(
+            lists.into_iter().map(MailingListResponse::new).collect(),
+        )),
+        Err(err) => Err(database_error("Failed to load mailing lists", err)),
+    }
+}
+
+pub async fn create_mailing_list(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Json(payload): Json<CreateMailingListRequest>,
+) -> Result<(StatusCode, Json<MailingListResponse>), ApiError> {
+    use crate::database::schema::mailing_lists;
+
+    validate_name(&payload.name)?;
+
+    let new_mailing_list = NewMailingList {
+        name: payload.name.trim(),
+        description: payload.description.as_deref().unwrap_or(""),
+    };
+
+    let mut conn = get_connection(&pool)?;
+
+    match diesel::insert_into(mailing_lists::table)
+        .values(&new_mailing_list)
+        .returning(MailingList::as_returning())
+        .get_result(&mut conn)
+    {
+        Ok(mailing_list) => Ok((
+            StatusCode::CREATED,
+            Json(MailingListResponse::new(mailing_list)),
+        )),
+        Err(err) => Err(database_error("Failed to create mailing list", err)),
+    }
+}
+
+pub async fn get_mailing_list(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path(mailing_list_id): Path<i32>,
+) -> Result<Json<MailingListResponse>, ApiError> {
+    use crate::database::schema::mailing_lists;
+
+    let mut conn = get_connection(&pool)?;
+
+    match mailing_lists::table
+        .find(mailing_list_id)
+        .first::<MailingList>(&mut conn)
+    {
+        Ok(mailing_list) => Ok(Json(MailingListResponse::new(mailing_list))),
+        Err(diesel::result::Error::NotFound) => Err(mailing_list_not_found(mailing_list_id)),
+        Err(err) => Err(database_error("Failed to load mailing list", err)),
+    }
+}
+
+pub async fn update_mailing_list(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path(mailing_list_id): Path<i32>,
+    Json(payload): Json<UpdateMailingListRequest>,
+) -> Result<Json<MailingListResponse>, ApiError> {
+    use crate::database::schema::mailing_lists;
+
+    if let Some(name) = &payload.name {
+        validate_name(name)?;
+    }
+
+    if payload.name.is_none() && payload.description.is_none() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            ApiErrorCode::ValidationError,
+            "Nothing to update, pass `name` and/or `description`".to_string(),
+            HashMap::new(),
+        ));
+    }
+
+    let changes = UpdateMailingList {
+        name: payload.name.as_deref().map(str::trim),
+        description: payload.description.as_deref(),
+    };
+
+    let mut conn = get_connection(&pool)?;
+
+    match diesel::update(mailing_lists::table.find(mailing_list_id))
+        .set(&changes)
+        .returning(MailingList::as_returning())
+        .get_result(&mut conn)
+    {
+        Ok(mailing_list) => Ok(Json(MailingListResponse::new(mailing_list))),
+        Err(diesel::result::Error::NotFound) => Err(mailing_list_not_found(mailing_list_id)),
+        Err(err) => Err(database_error("Failed to update mailing list", err)),
+    }
+}
+
+pub async fn delete_mailing_list(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path(mailing_list_id): Path<i32>,
+) -> Result<StatusCode, ApiError> {
+    use crate::database::schema::mailing_lists;
+
+    let mut conn = get_connection(&pool)?;
+
+    // Subscribers are removed by the `ON DELETE CASCADE` on the foreign key.
+    match diesel::delete(mailing_lists::table.find(mailing_list_id)).execute(&mut conn) {
+        Ok(0) => Err(mailing_list_not_found(mailing_list_id)),
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(database_error("Failed to delete mailing list", err)),
+    }
+}
+
+pub async fn get_subscribers(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path(mailing_list_id): Path<i32>,
+    Query(pagination): Query<PaginationQuery>,
+) -> Result<Json<SubscribersPageResponse>, ApiError> {
+    use crate::database::schema::{mailing_list_subscribers, mailing_lists};
+
+    const DEFAULT_PER_PAGE: i64 = 50;
+    const MAX_PER_PAGE: i64 = 500;
+
+    let page = pagination.page.unwrap_or(1).max(1);
+    let per_page = pagination
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+
+    let mut conn = get_connection(&pool)?;
+
+    match mailing_lists::table
+        .find(mailing_list_id)
+        .select(mailing_lists::id)
+        .first::<i32>(&mut conn)
+    {
+        Ok(_) => (),
+        Err(diesel::result::Error::NotFound) => {
+            return Err(mailing_list_not_found(mailing_list_id))
+        }
+        Err(err) => return Err(database_error("Failed to load mailing list", err)),
+    };
+
+    let total = match mailing_list_subscribers::table
+        .filter(mailing_list_subscribers::mailing_list_id.eq(mailing_list_id))
+        .count()
+        .get_result::<i64>(&mut conn)
+    {
+        Ok(total) => total,
+        Err(err) => return Err(database_error("Failed to count subscribers", err)),
+    };
+
+    let subscribers = match mailing_list_subscribers::table
+        .filter(mailing_list_subscribers::mailing_list_id.eq(mailing_list_id))
+        .order(mailing_list_subscribers::id.asc())
+        .limit(per_page)
+        .offset((page - 1) * per_page)
+        .load::<MailingListSubscriber>(&mut conn)
+    {
+        Ok(subscribers) => subscribers,
+        Err(err) => return Err(database_error("Failed to load subscribers", err)),
+    };
+
+    Ok(Json(SubscribersPageResponse {
+        subscribers: subscribers
+            .into_iter()
+            .map(SubscriberResponse::new)
+            .collect(),
+        page,
+        per_page,
+        total,
+    }))
+}
+
+pub async fn add_subscriber(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path(mailing_list_id): Path<i32>,
+    Json(payload): Json<AddSubscriberRequest>,
+) -> Result<(StatusCode, Json<SubscriberResponse>), ApiError> {
+    use crate::database::schema::mailing_list_subscribers;
+
+    let email = payload.email.trim();
+    let locale = payload
+        .locale
+        .as_deref()
+        .map(str::trim)
+        .filter(|locale| !locale.is_empty());
+
+    let mut errors = HashMap::new();
+    if email.parse::<Address>().is_err() {
+        errors.insert(
+            "email".to_string(),
+            format!("Invalid email address `{email}`"),
+        );
+    }
+    if let Some(locale) = locale {
+        if !locales::is_valid_locale(locale) {
+            errors.insert(
+                "locale".to_string(),
+                format!("Invalid locale `{locale}`, expected a language tag like `nl` or `pt-BR`"),
+            );
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            ApiErrorCode::ValidationError,
+            "One or more fields are invalid".to_string(),
+            errors,
+        ));
+    }
+
+    let new_subscriber = NewMailingListSubscriber {
+        email,
+        name: payload.name.as_deref().unwrap_or("").trim(),
+        mailing_list_id,
+        locale,
+    };
+
+    let mut conn = get_connection(&pool)?;
+
+    match diesel::insert_into(mailing_list_subscribers::table)
+        .values(&new_subscriber)
+        .returning(MailingListSubscriber::as_returning())
+        .get_result(&mut conn)
+    {
+        Ok(subscriber) => Ok((
+            StatusCode::CREATED,
+            Json(SubscriberResponse::new(subscriber)),
+        )),
+        Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Err(ApiError::new(
+                StatusCode::CONFLICT,
+                ApiErrorCode::Conflict,
+                format!("{email} is already subscribed to mailing list {mailing_list_id}"),
+                HashMap::new(),
+            ))
+        }
+        Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => {
+            Err(mailing_list_not_found(mailing_list_id))
+        }
+        Err(err) => Err(database_error("Failed to add subscriber", err)),
+    }
+}
+
+pub async fn remove_subscriber(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path((mailing_list_id, subscriber_id)): Path<(i32, i32)>,
+) -> Result<StatusCode, ApiError> {
+    use crate::database::schema::mailing_list_subscribers;
+
+    let mut conn = get_connection(&pool)?;
+
+    match diesel::delete(
+        mailing_list_subscribers::table
+            .filter(mailing_list_subscribers::id.eq(subscriber_id))
+            .filter(mailing_list_subscribers::mailing_list_id.eq(mailing_list_id)),
+    )
+    .execute(&mut conn)
+    {
+        Ok(0) => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            ApiErrorCode::NotFound,
+            format!("Subscriber {subscriber_id} not found in mailing list {mailing_list_id}"),
+            HashMap::new(),
+        )),
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(database_error("Failed to remove subscriber", err)),
+    }
+}
+
+pub async fn send_to_mailing_list(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Extension(api_key): Extension<AuthenticatedKey>,
+    Extension(rate_limit): Extension<Arc<SendRateLimit>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Path(mailing_list_id): Path<i32>,
+    Json(mut payload): Json<SendMailingListMailRequest>,
+) -> Result<Json<Vec<SendMailResponse>>, ApiError> {
+    use crate::database::schema::{mailing_list_subscribers, mailing_lists};
+
+    let attachments = decode_attachments(payload.attachments.take().unwrap_or_default())?;
+
+    let mut conn = get_connection(&pool)?;
+
+    match mailing_lists::table
+        .find(mailing_list_id)
+        .select(mailing_lists::id)
+        .first::<i32>(&mut conn)
+    {
+        Ok(_) => (),
+        Err(diesel::result::Error::NotFound) => {
+            return Err(mailing_list_not_found(mailing_list_id))
+        }
+        Err(err) => return Err(database_error("Failed to load mailing list", err)),
+    };
+
+    let subscribers = match mailing_list_subscribers::table
+        .filter(mailing_list_subscribers::mailing_list_id.eq(mailing_list_id))
+        .order(mailing_list_subscribers::id.asc())
+        .load::<MailingListSubscriber>(&mut conn)
+    {
+        Ok(subscribers) => subscribers,
+        Err(err) => return Err(database_error("Failed to load subscribers", err)),
+    };
+
+    // Subscribers that unsubscribed, from this list or any other, are skipped.
+    let subscriber_emails: Vec<String> = subscribers
+        .iter()
+        .map(|subscriber| subscriber.email.clone())
+        .collect();
+    let suppressed = match unsubscribe::find_suppressed(&mut conn, &subscriber_emails) {
+        Ok(suppressed) => suppressed,
+        Err(err) => return Err(database_error("Failed to load suppressions", err)),
+    };
+
+    let subscribers: Vec<MailingListSubscriber> = subscribers
+        .into_iter()
+        .filter(|subscriber| {
+            !suppressed.contains(&unsubscribe::normalize_address(&subscriber.email))
+        })
+        .collect();
+
+    acquire_send_rate_limit(&rate_limit, &api_key, address, subscribers.len())?;
+
+    // Render every mail up front, so we don't hold a transaction open while rendering. Rendering
+    // reads the templates from disk, so it runs on the blocking thread pool.
+    let prepared_mails = match tokio::task::spawn_blocking(move || {
+        subscribers
+            .iter()
+            .map(|subscriber| {
+                // A subscriber the mail can't be prepared for, for example because their data is
+                // missing a required variable, fails the whole send, and is named in the error.
+                prepare_mail(
+                    payload.for_subscriber(mailing_list_id, subscriber),
+                    &api_key,
+                )
+                .map_err(|mut err| {
+                    err.details
+                        .insert("subscriber".to_string(), subscriber.email.clone());
+                    err
+                })
+            })
+            .collect::<Result<Vec<PreparedMail>, ApiError>>()
+    })
+    .await
+    {
+        Ok(prepared_mails) => prepared_mails?,
+        Err(err) => {
+            return Err(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ApiErrorCode::Unknown,
+                "Failed to render mails: ".to_string() + &err.to_string(),
+                HashMap::new(),
+            ))
+        }
+    };
+
+    // Every subscriber gets the same attachments, so they are stored once and shared.
+    let (shared_id, shared_attachments) = if attachments.is_empty() || prepared_mails.is_empty() {
+        (None, vec![])
+    } else {
+        let (shared_id, shared_attachments) = store_shared_attachments(attachments)?;
+        (Some(shared_id), shared_attachments)
+    };
+
+    // Either every prepared mail is queued, or none of them are.
+    let stored_mails = match conn.transaction(|conn| {
+        let mut stored_mails = Vec::with_capacity(prepared_mails.len());
+        for prepared_mail in prepared_mails {
+            stored_mails.push(insert_mail_with_shared_attachments(
+                conn,
+                &prepared_mail,
+                &shared_attachments,
+            )?);
+        }
+        Ok(stored_mails)
+    }) {
+        Ok(stored_mails) => stored_mails,
+        Err(err) => {
+            // The rows are rolled back, but the attachment files were already written.
+            if let Some(shared_id) = shared_id {
+                attachments::remove_shared_attachments(&shared_id);
+            }
+            return Err(database_error("Failed to save mails", err));
+        }
+    };
+
+    Ok(Json(
+        stored_mails
+            .into_iter()
+            .map(SendMailResponse::new)
+            .collect(),
+    ))
+}
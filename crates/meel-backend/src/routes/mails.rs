@@ -1,22 +1,49 @@
-use crate::database;
-use crate::database::models::{Mail, NewMail};
-use axum::extract::Path;
+use crate::auth::AuthenticatedKey;
+use crate::database::models::{
+    Mail, MailAttachment, MailEvent, MailRecipient, NewMail, NewMailAttachment, UpdateMail,
+};
+use crate::mail_events::{record_event, record_event_at, MailEventType};
+use crate::mail_recipients::{insert_recipients, load_recipients, RecipientKind};
+use crate::mail_status::MailStatus;
+use crate::rate_limiter::SendRateLimit;
+use crate::routes::extract::{Json, Path, Query};
+use crate::routes::templates::template_error;
+use crate::routes::{database_error, get_connection};
+use crate::{allowed_senders, attachments, database, mail_headers, unsubscribe};
+use axum::extract::ConnectInfo;
 use axum::http::StatusCode;
 use axum::response::Html;
-use axum::{Extension, Json};
-use diesel::{QueryDsl, RunQueryDsl, SelectableHelper};
-use meel_templating::templating;
-use meel_templating::templating::TemplateDataMap;
+use axum::Extension;
+use base64::Engine;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension,
+    PgArrayExpressionMethods, PgConnection, PgJsonbExpressionMethods, QueryDsl, RunQueryDsl,
+    SelectableHelper,
+};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use meel_templating::templating::{TemplateDataMap, TemplateError};
+use meel_templating::{locales, plain_text, templating};
 use meel_utils::api_error::{ApiError, ApiErrorCode};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Deserialize)]
 pub struct SendMailRequest {
+    /// The main recipient, may be left out when `to` is passed instead.
+    #[serde(default)]
     pub recipient: String,
+    pub to: Option<Vec<String>>,
+    pub cc: Option<Vec<String>>,
+    pub bcc: Option<Vec<String>>,
+    /// May be left out when the template declares a sender in its front matter.
+    #[serde(default)]
     pub sender: String,
+    /// May be left out when the template declares a subject in its front matter.
+    #[serde(default)]
     pub subject: String,
     pub template: String,
     pub priority: i32,
@@ -25,7 +52,78 @@ pub struct SendMailRequest {
     pub minify_html: Option<bool>,
     pub schedule_at: Option<String>,
     pub reply_to: Option<String>,
-    // TODO: Handle attachments
+    pub attachments: Option<Vec<AttachmentRequest>>,
+    /// Extra headers, limited to custom `X-` headers and a few list and threading headers.
+    pub headers: Option<HashMap<String, String>>,
+    /// `https:` URLs and/or `mailto:` addresses for the `List-Unsubscribe` header.
+    pub list_unsubscribe: Option<Vec<String>>,
+    /// Add `List-Unsubscribe-Post`, to allow one-click unsubscribing (RFC 8058).
+    pub list_unsubscribe_one_click: Option<bool>,
+    pub tags: Option<Vec<String>>,
+    pub metadata: Option<HashMap<String, String>>,
+    /// Render the `<template>.<locale>` variant of the template and translations for this locale.
+    pub locale: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AttachmentRequest {
+    pub file_name: String,
+    pub file_type: String,
+    /// The base64 encoded contents of the file.
+    pub data: String,
+}
+
+pub struct DecodedAttachment {
+    file_name: String,
+    file_type: String,
+    contents: Vec<u8>,
+}
+
+#[derive(Serialize)]
+pub struct AttachmentResponse {
+    id: i32,
+    file_name: String,
+    file_type: String,
+    file_size: i32,
+}
+
+impl AttachmentResponse {
+    fn new(attachment: MailAttachment) -> Self {
+        Self {
+            id: attachment.id,
+            file_name: attachment.file_name,
+            file_type: attachment.file_type,
+            file_size: attachment.file_size,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RecipientResponse {
+    kind: String,
+    address: String,
+    status: String,
+    smtp_code: Option<i32>,
+    last_error: Option<String>,
+}
+
+impl RecipientResponse {
+    fn new(recipient: MailRecipient) -> Self {
+        Self {
+            kind: recipient.kind,
+            address: recipient.address,
+            status: recipient.status,
+            smtp_code: recipient.smtp_code,
+            last_error: recipient.last_error,
+        }
+    }
+}
+
+/// A mail together with the rows that belong to it.
+pub struct StoredMail {
+    pub mail: Mail,
+    pub attachments: Vec<MailAttachment>,
+    pub recipients: Vec<MailRecipient>,
 }
 
 #[derive(Serialize)]
@@ -33,97 +131,621 @@ pub struct SendMailResponse {
     id: i32,
     sender: String,
     recipient: String,
+    subject: String,
+    template: Option<String>,
     send_attempts: i32,
     priority: i32,
+    created_at: String,
     scheduled_at: String,
     sent_at: Option<String>,
     sent: bool,
-    // TODO: Attachment information
+    status: MailStatus,
+    last_error: Option<String>,
+    headers: serde_json::Value,
+    tags: Vec<String>,
+    metadata: serde_json::Value,
+    recipients: Vec<RecipientResponse>,
+    attachments: Vec<AttachmentResponse>,
 }
 
 impl SendMailResponse {
-    fn new(mail: Mail) -> Self {
+    pub fn new(stored_mail: StoredMail) -> Self {
+        let StoredMail {
+            mail,
+            attachments,
+            recipients,
+        } = stored_mail;
+
         Self {
             id: mail.id,
             sender: mail.sender,
             recipient: mail.recipient,
+            subject: mail.subject,
+            template: mail.template,
             send_attempts: mail.send_attempts,
             priority: mail.priority,
+            created_at: meel_utils::time::system_time_to_iso_string(mail.created_at),
             scheduled_at: meel_utils::time::system_time_to_iso_string(mail.scheduled_at),
             sent_at: mail
                 .sent_at
                 .map(meel_utils::time::system_time_to_iso_string),
             sent: mail.sent_at.is_some(),
+            // The database only allows known statuses, so this can't fall back in practice.
+            status: mail.status.parse().unwrap_or(MailStatus::Queued),
+            last_error: mail.last_error,
+            headers: mail.headers,
+            tags: mail.tags,
+            metadata: mail.metadata,
+            recipients: recipients.into_iter().map(RecipientResponse::new).collect(),
+            attachments: attachments
+                .into_iter()
+                .map(AttachmentResponse::new)
+                .collect(),
         }
     }
 }
 
-pub async fn send_mail(
-    pool: Extension<Arc<database::ConnectionPool>>,
-    mail: SendMailRequest,
-) -> Result<Mail, ApiError> {
-    use crate::database::schema::mails;
+pub fn decode_attachments(
+    attachments: Vec<AttachmentRequest>,
+) -> Result<Vec<DecodedAttachment>, ApiError> {
+    const DEFAULT_MAX_ATTACHMENT_SIZE: usize = 10 * 1024 * 1024;
+    let max_attachment_size = meel_utils::env::get_var(
+        "MEEL_MAX_ATTACHMENT_SIZE",
+        Some(&DEFAULT_MAX_ATTACHMENT_SIZE.to_string()),
+    )
+    .unwrap()
+    .parse::<usize>()
+    .unwrap_or(DEFAULT_MAX_ATTACHMENT_SIZE);
 
-    let html_body_string = match templating::render(
-        mail.template.clone(),
-        mail.data.clone(),
-        mail.allow_html.unwrap_or(false),
-        mail.minify_html.unwrap_or(true),
-    ) {
-        Ok(html_body_string) => html_body_string,
-        Err(err) => {
-            return Err(ApiError::new(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                ApiErrorCode::Unknown,
-                "Could not render template: ".to_string() + &err.to_string(),
-                HashMap::new(),
-            ))
-        }
-    };
-    let plain_text_string = templating::render_plain_text(mail.template, mail.data.clone())
-        .unwrap_or_else(|_| "".to_string());
+    let mut decoded_attachments = vec![];
 
-    let scheduled_at = if mail.schedule_at.is_some() {
-        let iso_string = match mail.schedule_at.as_ref() {
-            Some(iso_string) => iso_string,
+    for attachment in attachments {
+        let file_name = match attachments::sanitize_file_name(&attachment.file_name) {
+            Some(file_name) => file_name,
             None => {
                 return Err(ApiError::new(
                     StatusCode::BAD_REQUEST,
-                    ApiErrorCode::Unknown,
-                    "Missing `schedule_at`".to_string(),
+                    ApiErrorCode::ValidationError,
+                    format!("Invalid attachment file name `{}`", attachment.file_name),
                     HashMap::new(),
                 ))
             }
         };
 
-        match meel_utils::time::iso_string_to_system_time(iso_string) {
-            Ok(scheduled_at) => scheduled_at,
+        if ContentType::parse(&attachment.file_type).is_err() {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                ApiErrorCode::ValidationError,
+                format!("Invalid file type for attachment `{file_name}`"),
+                HashMap::new(),
+            ));
+        }
+
+        let contents = match base64::engine::general_purpose::STANDARD.decode(attachment.data) {
+            Ok(contents) => contents,
             Err(err) => {
                 return Err(ApiError::new(
                     StatusCode::BAD_REQUEST,
-                    ApiErrorCode::Unknown,
-                    "Failed to parse `schedule_at`: ".to_string() + &err.to_string(),
+                    ApiErrorCode::ValidationError,
+                    format!("Failed to decode attachment `{file_name}`: {err}"),
                     HashMap::new(),
                 ))
             }
+        };
+
+        if contents.len() > max_attachment_size {
+            return Err(ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ApiErrorCode::ValidationError,
+                format!(
+                    "Attachment `{file_name}` exceeds the maximum size of {max_attachment_size} bytes"
+                ),
+                HashMap::new(),
+            ));
         }
-    } else {
-        SystemTime::now()
+
+        decoded_attachments.push(DecodedAttachment {
+            file_name,
+            file_type: attachment.file_type,
+            contents,
+        });
+    }
+
+    Ok(decoded_attachments)
+}
+
+/// Write the attachments to the data directory, and link them to the mail.
+fn save_attachments(
+    conn: &mut PgConnection,
+    mail_id: i32,
+    attachments: &[DecodedAttachment],
+) -> Result<Vec<MailAttachment>, diesel::result::Error> {
+    use crate::database::schema::mail_attachments;
+
+    let mut saved_attachments = vec![];
+
+    for (index, attachment) in attachments.iter().enumerate() {
+        let file_path = match attachments::store_attachment(
+            mail_id,
+            index,
+            &attachment.file_name,
+            &attachment.contents,
+        ) {
+            Ok(file_path) => file_path,
+            Err(err) => {
+                tracing::error!("{}", err);
+                return Err(diesel::result::Error::RollbackTransaction);
+            }
+        };
+
+        let new_attachment = NewMailAttachment {
+            mail_id,
+            file_name: &attachment.file_name,
+            file_type: &attachment.file_type,
+            file_size: attachment.contents.len() as i32,
+            file_path: &file_path,
+        };
+
+        saved_attachments.push(
+            diesel::insert_into(mail_attachments::table)
+                .values(&new_attachment)
+                .returning(MailAttachment::as_returning())
+                .get_result(conn)?,
+        );
+    }
+
+    Ok(saved_attachments)
+}
+
+/// An attachment that is written to disk once, and shared by several mails.
+pub struct SharedAttachment {
+    file_name: String,
+    file_type: String,
+    file_size: i32,
+    file_path: String,
+}
+
+/// Write attachments for a group of mails that all get them, such as a mailing list send. Returns
+/// the id of the directory they are stored in, along with the attachments.
+pub fn store_shared_attachments(
+    attachments: Vec<DecodedAttachment>,
+) -> Result<(String, Vec<SharedAttachment>), ApiError> {
+    let storage_error = |err: String| {
+        tracing::error!("{}", err);
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ApiErrorCode::Unknown,
+            err,
+            HashMap::new(),
+        )
     };
 
-    // TODO: Parse the subject from the template if it is not passed by the user.
+    let shared_id = attachments::create_shared_directory().map_err(storage_error)?;
+    let mut shared_attachments = vec![];
+
+    for (index, attachment) in attachments.into_iter().enumerate() {
+        match attachments::store_shared_attachment(
+            &shared_id,
+            index,
+            &attachment.file_name,
+            &attachment.contents,
+        ) {
+            Ok(file_path) => shared_attachments.push(SharedAttachment {
+                file_size: attachment.contents.len() as i32,
+                file_name: attachment.file_name,
+                file_type: attachment.file_type,
+                file_path,
+            }),
+            Err(err) => {
+                attachments::remove_shared_attachments(&shared_id);
+                return Err(storage_error(err));
+            }
+        }
+    }
 
-    if mail.subject.is_empty() || mail.subject.trim().len() < 6 {
+    Ok((shared_id, shared_attachments))
+}
+
+fn validate_subject(subject: &str) -> Result<(), ApiError> {
+    if subject.trim().is_empty() {
         return Err(ApiError::new(
             StatusCode::BAD_REQUEST,
-            ApiErrorCode::Unknown,
-            "Missing or invalid `subject`".to_string(),
+            ApiErrorCode::ValidationError,
+            "Missing `subject`, pass one or declare it in the front matter of the template"
+                .to_string(),
             HashMap::new(),
         ));
     }
 
+    Ok(())
+}
+
+/// Check that an address parses as a mailbox, either `a@b.c` or `Name <a@b.c>`, recording an
+/// error for the field when it doesn't.
+fn validate_address(field: &str, address: &str, errors: &mut HashMap<String, String>) {
+    if address.trim().is_empty() {
+        errors.insert(field.to_string(), "Missing email address".to_string());
+        return;
+    }
+
+    if let Err(err) = address.parse::<Mailbox>() {
+        errors.insert(
+            field.to_string(),
+            format!("Invalid email address `{address}`: {err}"),
+        );
+    }
+}
+
+fn validation_error(errors: HashMap<String, String>) -> ApiError {
+    ApiError::new(
+        StatusCode::BAD_REQUEST,
+        ApiErrorCode::ValidationError,
+        "One or more fields are invalid".to_string(),
+        errors,
+    )
+}
+
+/// All recipients of a mail request, together with the name of the field each one came from.
+fn get_recipients(mail: &SendMailRequest) -> Vec<(String, RecipientKind, String)> {
+    let mut recipients = vec![];
+
+    if !mail.recipient.trim().is_empty() {
+        recipients.push((
+            "recipient".to_string(),
+            RecipientKind::To,
+            mail.recipient.clone(),
+        ));
+    }
+
+    for (field, kind, addresses) in [
+        ("to", RecipientKind::To, &mail.to),
+        ("cc", RecipientKind::Cc, &mail.cc),
+        ("bcc", RecipientKind::Bcc, &mail.bcc),
+    ] {
+        for (index, address) in addresses.iter().flatten().enumerate() {
+            recipients.push((format!("{field}[{index}]"), kind, address.clone()));
+        }
+    }
+
+    recipients
+}
+
+fn get_max_recipients() -> usize {
+    const DEFAULT_MAX_RECIPIENTS: usize = 50;
+    meel_utils::env::get_var(
+        "MEEL_MAX_RECIPIENTS",
+        Some(&DEFAULT_MAX_RECIPIENTS.to_string()),
+    )
+    .unwrap()
+    .parse::<usize>()
+    .unwrap_or(DEFAULT_MAX_RECIPIENTS)
+}
+
+/// Validate every address of a mail, so malformed addresses are rejected up front instead of
+/// failing in the scheduler.
+pub fn validate_addresses(mail: &SendMailRequest) -> Result<(), ApiError> {
+    let mut errors = HashMap::new();
+
+    validate_address("sender", &mail.sender, &mut errors);
+
+    let recipients = get_recipients(mail);
+    if !recipients
+        .iter()
+        .any(|(_, kind, _)| *kind == RecipientKind::To)
+    {
+        errors.insert(
+            "recipient".to_string(),
+            "Pass a `recipient` or at least one `to` address".to_string(),
+        );
+    }
+
+    let max_recipients = get_max_recipients();
+    if recipients.len() > max_recipients {
+        errors.insert(
+            "recipients".to_string(),
+            format!("A mail can have at most {max_recipients} recipients"),
+        );
+    }
+
+    for (field, _, address) in &recipients {
+        validate_address(field, address, &mut errors);
+    }
+
+    if let Some(reply_to) = &mail.reply_to {
+        validate_address("reply_to", reply_to, &mut errors);
+    }
+
+    if !errors.is_empty() {
+        return Err(validation_error(errors));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_validate_addresses() {
+    fn request(value: serde_json::Value) -> SendMailRequest {
+        let mut request = serde_json::json!({
+            "sender": "me@example.com",
+            "subject": "Hello world",
+            "template": "hello",
+            "priority": 0,
+            "data": {},
+        });
+        request
+            .as_object_mut()
+            .unwrap()
+            .extend(value.as_object().unwrap().clone());
+
+        serde_json::from_value(request).unwrap()
+    }
+
+    assert!(validate_addresses(&request(serde_json::json!({
+        "recipient": "Boris <boris@example.com>",
+    })))
+    .is_ok());
+    assert!(validate_addresses(&request(serde_json::json!({
+        "sender": "\"Meel, Inc.\" <noreply@example.com>",
+        "to": ["boris@example.com", "John <john@example.com>"],
+        "cc": ["manager@example.com"],
+        "reply_to": "support@example.com",
+    })))
+    .is_ok());
+
+    let err = validate_addresses(&request(serde_json::json!({
+        "sender": "not an address",
+        "recipient": "Boris <boris@>",
+        "bcc": ["", "audit@example.com"],
+        "reply_to": "",
+    })))
+    .unwrap_err();
+    assert_eq!(err.details.len(), 4);
+    assert!(err.details.contains_key("sender"));
+    assert!(err.details.contains_key("recipient"));
+    assert!(err.details.contains_key("bcc[0]"));
+    assert!(err.details.contains_key("reply_to"));
+
+    // Cc and bcc recipients alone aren't enough.
+    let err = validate_addresses(&request(serde_json::json!({
+        "cc": ["manager@example.com"],
+    })))
+    .unwrap_err();
+    assert!(err.details.contains_key("recipient"));
+}
+
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LENGTH: usize = 64;
+const MAX_METADATA_KEYS: usize = 20;
+const MAX_METADATA_KEY_LENGTH: usize = 64;
+const MAX_METADATA_VALUE_LENGTH: usize = 512;
+
+/// Trim and deduplicate the tags of a mail, recording an error for every invalid tag.
+fn collect_tags(tags: &[String], errors: &mut HashMap<String, String>) -> Vec<String> {
+    if tags.len() > MAX_TAGS {
+        errors.insert(
+            "tags".to_string(),
+            format!("A mail can have at most {MAX_TAGS} tags"),
+        );
+    }
+
+    let mut collected_tags: Vec<String> = vec![];
+
+    for (index, tag) in tags.iter().enumerate() {
+        let tag = tag.trim();
+
+        if tag.is_empty() || tag.len() > MAX_TAG_LENGTH || tag.contains(',') {
+            errors.insert(
+                format!("tags[{index}]"),
+                format!("Tags must be 1 to {MAX_TAG_LENGTH} characters, without commas"),
+            );
+        } else if !collected_tags.iter().any(|collected| collected == tag) {
+            collected_tags.push(tag.to_string());
+        }
+    }
+
+    collected_tags
+}
+
+fn validate_metadata(metadata: &HashMap<String, String>, errors: &mut HashMap<String, String>) {
+    if metadata.len() > MAX_METADATA_KEYS {
+        errors.insert(
+            "metadata".to_string(),
+            format!("A mail can have at most {MAX_METADATA_KEYS} metadata keys"),
+        );
+    }
+
+    for (key, value) in metadata {
+        // Keys can't contain a colon, as that separates the key and value when filtering.
+        if key.trim().is_empty() || key.len() > MAX_METADATA_KEY_LENGTH || key.contains(':') {
+            errors.insert(
+                format!("metadata.{key}"),
+                format!("Keys must be 1 to {MAX_METADATA_KEY_LENGTH} characters, without colons"),
+            );
+        } else if value.len() > MAX_METADATA_VALUE_LENGTH {
+            errors.insert(
+                format!("metadata.{key}"),
+                format!("Values can be at most {MAX_METADATA_VALUE_LENGTH} characters"),
+            );
+        }
+    }
+}
+
+/// The headers, tags and metadata of a mail request, validated and in the form they are stored.
+fn prepare_headers_and_tags(
+    mail: &SendMailRequest,
+) -> Result<(serde_json::Value, Vec<String>, serde_json::Value), ApiError> {
+    let mut errors = HashMap::new();
+
+    let headers = mail_headers::collect_headers(
+        mail.headers.as_ref().unwrap_or(&HashMap::new()),
+        mail.list_unsubscribe.as_deref().unwrap_or_default(),
+        mail.list_unsubscribe_one_click.unwrap_or(false),
+        &mut errors,
+    );
+    let tags = collect_tags(mail.tags.as_deref().unwrap_or_default(), &mut errors);
+
+    let metadata = mail.metadata.clone().unwrap_or_default();
+    validate_metadata(&metadata, &mut errors);
+
+    if !errors.is_empty() {
+        return Err(validation_error(errors));
+    }
+
+    Ok((
+        serde_json::json!(headers),
+        tags,
+        serde_json::json!(metadata),
+    ))
+}
+
+#[test]
+fn test_collect_tags() {
+    let mut errors = HashMap::new();
+    let tags = collect_tags(
+        &[
+            " newsletter ".to_string(),
+            "spring".to_string(),
+            "newsletter".to_string(),
+            "".to_string(),
+            "a,b".to_string(),
+        ],
+        &mut errors,
+    );
+
+    assert_eq!(tags, vec!["newsletter", "spring"]);
+    assert_eq!(errors.len(), 2);
+    assert!(errors.contains_key("tags[3]"));
+    assert!(errors.contains_key("tags[4]"));
+}
+
+fn parse_schedule_at(iso_string: &str) -> Result<SystemTime, ApiError> {
+    meel_utils::time::iso_string_to_system_time(iso_string).map_err(|err| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            ApiErrorCode::ValidationError,
+            "Failed to parse `schedule_at`: ".to_string() + &err.to_string(),
+            HashMap::new(),
+        )
+    })
+}
+
+/// A rendered mail, ready to be inserted into the database.
+pub struct PreparedMail {
+    template: String,
+    sender: String,
+    /// The first `to` address, shown as the recipient of the mail.
+    recipient: String,
+    recipients: Vec<(RecipientKind, String)>,
+    subject: String,
+    html_body: String,
+    text_body: String,
+    priority: i32,
+    scheduled_at: SystemTime,
+    reply_to: Option<String>,
+    headers: serde_json::Value,
+    tags: Vec<String>,
+    metadata: serde_json::Value,
+    /// When the template was rendered, recorded as the `rendered` event once the mail is stored.
+    rendered_at: SystemTime,
+}
+
+/// Render the template and subject of a mail request, and validate its fields. The sender,
+/// reply-to address and subject default to those in the front matter of the template, so the
+/// sender is only checked against the API key here.
+pub fn prepare_mail(
+    mut mail: SendMailRequest,
+    api_key: &AuthenticatedKey,
+) -> Result<PreparedMail, ApiError> {
+    if let Some(locale) = &mail.locale {
+        if !locales::is_valid_locale(locale) {
+            return Err(validation_error(HashMap::from([(
+                "locale".to_string(),
+                format!("Invalid locale `{locale}`, expected a language tag like `nl` or `pt-BR`"),
+            )])));
+        }
+    }
+    let locale = mail.locale.as_deref();
+
+    let recipients: Vec<(RecipientKind, String)> = get_recipients(&mail)
+        .into_iter()
+        .map(|(_, kind, address)| (kind, address))
+        .collect();
+    // Validation below guarantees there is at least one `to` address.
+    let recipient = recipients
+        .iter()
+        .find(|(kind, _)| *kind == RecipientKind::To)
+        .map(|(_, address)| address.clone())
+        .unwrap_or_default();
+
+    // Mailing list sends pass a link to unsubscribe from the list, other mails get a link to
+    // unsubscribe the main recipient from all mail.
+    if !mail.data.contains_key("unsubscribe_url") {
+        if let Some(unsubscribe_url) = unsubscribe::get_unsubscribe_url(None, &recipient) {
+            mail.data.insert(
+                "unsubscribe_url".to_string(),
+                serde_json::Value::String(unsubscribe_url),
+            );
+        }
+    }
+
+    let rendered = templating::render(
+        mail.template.clone(),
+        mail.data.clone(),
+        mail.allow_html.unwrap_or(false),
+        mail.minify_html.unwrap_or(true),
+        locale,
+    )
+    .map_err(template_error)?;
+    let front_matter = rendered.front_matter;
+
+    if mail.sender.trim().is_empty() {
+        mail.sender = front_matter.sender.unwrap_or_default();
+    }
+    if mail.reply_to.is_none() {
+        mail.reply_to = front_matter.reply_to;
+    }
+    if mail.subject.trim().is_empty() {
+        mail.subject = front_matter.subject.unwrap_or_default();
+    }
+    // The category is stored as a tag, so mails can be filtered by it.
+    if let Some(category) = front_matter.category {
+        mail.tags.get_or_insert_with(Vec::new).push(category);
+    }
+
+    validate_addresses(&mail)?;
+    allowed_senders::check_sender(api_key, &mail.sender)?;
+    let (headers, tags, metadata) = prepare_headers_and_tags(&mail)?;
+    // Without a plain text template, the text is generated from the HTML that was just rendered.
+    let plain_text_string =
+        match templating::render_plain_text(mail.template.clone(), mail.data.clone(), locale) {
+            Ok(plain_text) => plain_text,
+            Err(TemplateError::NotFound(_)) => plain_text::html_to_text(&rendered.html),
+            Err(err) => return Err(template_error(err)),
+        };
+    let rendered_at = SystemTime::now();
+
+    let scheduled_at = if mail.schedule_at.is_some() {
+        let iso_string = match mail.schedule_at.as_ref() {
+            Some(iso_string) => iso_string,
+            None => {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    ApiErrorCode::ValidationError,
+                    "Missing `schedule_at`".to_string(),
+                    HashMap::new(),
+                ))
+            }
+        };
+
+        parse_schedule_at(iso_string)?
+    } else {
+        SystemTime::now()
+    };
+
+    validate_subject(&mail.subject)?;
+
     let subject = templating::apply_placeholders(
-        mail.subject,
+        locales::translate(&mail.subject, locale).map_err(template_error)?,
         mail.data.clone(),
         // Setting allow_html to true here is a bit of a hack, as if we don't it will replace spaces
         // and special characters with html equivalents, which we don't want.
@@ -131,96 +753,335 @@ pub async fn send_mail(
     )
     .map_err(|err| {
         ApiError::new(
-            StatusCode::BAD_REQUEST,
-            ApiErrorCode::Unknown,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ApiErrorCode::TemplateRenderError,
             "Failed to apply placeholders to subject: ".to_string() + &err.to_string(),
             HashMap::new(),
         )
     })?;
 
+    Ok(PreparedMail {
+        template: mail.template,
+        sender: mail.sender,
+        recipient,
+        recipients,
+        subject,
+        html_body: rendered.html,
+        text_body: plain_text_string,
+        priority: mail.priority,
+        scheduled_at,
+        reply_to: mail.reply_to,
+        headers,
+        tags,
+        metadata,
+        rendered_at,
+    })
+}
+
+fn insert_mail_and_recipients(
+    conn: &mut PgConnection,
+    mail: &PreparedMail,
+) -> Result<(Mail, Vec<MailRecipient>), diesel::result::Error> {
+    use crate::database::schema::mails;
+
     let new_mail = NewMail {
         sender: &mail.sender,
         recipient: &mail.recipient,
-        subject: &subject,
-        html_body: &html_body_string,
-        text_body: &plain_text_string,
+        subject: &mail.subject,
+        html_body: &mail.html_body,
+        text_body: &mail.text_body,
         send_attempts: 0,
         priority: mail.priority,
         reply_to: mail.reply_to.as_deref(),
-        scheduled_at,
+        scheduled_at: mail.scheduled_at,
+        template: Some(&mail.template),
+        headers: &mail.headers,
+        tags: &mail.tags,
+        metadata: &mail.metadata,
     };
 
-    let mut conn = match pool.get() {
-        Ok(conn) => conn,
+    let created_mail = diesel::insert_into(mails::table)
+        .values(&new_mail)
+        .returning(Mail::as_returning())
+        .get_result(conn)?;
+
+    let recipients = insert_recipients(conn, created_mail.id, &mail.recipients)?;
+
+    record_event_at(
+        conn,
+        created_mail.id,
+        MailEventType::Rendered,
+        mail.rendered_at,
+    )?;
+    record_event(conn, created_mail.id, MailEventType::Queued, None, None)?;
+
+    Ok((created_mail, recipients))
+}
+
+/// Insert a prepared mail and its attachments. This should be called from within a transaction,
+/// so a failure to store one of the attachments doesn't leave a partial mail behind.
+pub fn insert_mail(
+    conn: &mut PgConnection,
+    mail: &PreparedMail,
+    attachments: &[DecodedAttachment],
+) -> Result<StoredMail, diesel::result::Error> {
+    let (created_mail, recipients) = insert_mail_and_recipients(conn, mail)?;
+
+    match save_attachments(conn, created_mail.id, attachments) {
+        Ok(saved_attachments) => Ok(StoredMail {
+            mail: created_mail,
+            attachments: saved_attachments,
+            recipients,
+        }),
         Err(err) => {
+            attachments::remove_attachments(created_mail.id);
+            Err(err)
+        }
+    }
+}
+
+/// Insert a prepared mail, linked to attachments that were already stored for it and other mails.
+pub fn insert_mail_with_shared_attachments(
+    conn: &mut PgConnection,
+    mail: &PreparedMail,
+    attachments: &[SharedAttachment],
+) -> Result<StoredMail, diesel::result::Error> {
+    use crate::database::schema::mail_attachments;
+
+    let (created_mail, recipients) = insert_mail_and_recipients(conn, mail)?;
+
+    let new_attachments: Vec<NewMailAttachment> = attachments
+        .iter()
+        .map(|attachment| NewMailAttachment {
+            mail_id: created_mail.id,
+            file_name: &attachment.file_name,
+            file_type: &attachment.file_type,
+            file_size: attachment.file_size,
+            file_path: &attachment.file_path,
+        })
+        .collect();
+
+    let saved_attachments = diesel::insert_into(mail_attachments::table)
+        .values(&new_attachments)
+        .returning(MailAttachment::as_returning())
+        .get_results(conn)?;
+
+    Ok(StoredMail {
+        mail: created_mail,
+        attachments: saved_attachments,
+        recipients,
+    })
+}
+
+/// Reject a mail when any of its recipients unsubscribed.
+fn check_suppressions(
+    conn: &mut PgConnection,
+    recipients: Vec<(String, RecipientKind, String)>,
+) -> Result<(), ApiError> {
+    let addresses: Vec<String> = recipients
+        .iter()
+        .map(|(_, _, address)| address.clone())
+        .collect();
+
+    let suppressed = unsubscribe::find_suppressed(conn, &addresses)
+        .map_err(|err| database_error("Failed to load suppressions", err))?;
+
+    if suppressed.is_empty() {
+        return Ok(());
+    }
+
+    let details: HashMap<String, String> = recipients
+        .into_iter()
+        .filter(|(_, _, address)| suppressed.contains(&unsubscribe::normalize_address(address)))
+        .map(|(field, _, address)| (field, format!("`{address}` unsubscribed")))
+        .collect();
+
+    Err(ApiError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        ApiErrorCode::RecipientSuppressed,
+        "One or more recipients unsubscribed".to_string(),
+        details,
+    ))
+}
+
+pub async fn send_mail(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    api_key: &AuthenticatedKey,
+    mut mail: SendMailRequest,
+) -> Result<StoredMail, ApiError> {
+    let mut conn = get_connection(&pool)?;
+
+    check_suppressions(&mut conn, get_recipients(&mail))?;
+
+    let attachments = decode_attachments(mail.attachments.take().unwrap_or_default())?;
+    let prepared_mail = prepare_mail(mail, api_key)?;
+
+    match conn.transaction(|conn| insert_mail(conn, &prepared_mail, &attachments)) {
+        Ok(created) => Ok(created),
+        Err(err) => Err(database_error("Failed to save mail", err)),
+    }
+}
+
+/// Count `count` mails against the send rate limit of the API key.
+pub fn acquire_send_rate_limit(
+    rate_limit: &SendRateLimit,
+    api_key: &AuthenticatedKey,
+    address: SocketAddr,
+    count: usize,
+) -> Result<(), ApiError> {
+    // Waiting won't help when a single request is over the limit.
+    if let Some(limit) = rate_limit.limit() {
+        if count > limit as usize {
             return Err(ApiError::new(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                ApiErrorCode::Unknown,
-                "Could not connect to database: ".to_string() + &err.to_string(),
+                StatusCode::BAD_REQUEST,
+                ApiErrorCode::ValidationError,
+                format!(
+                    "Can't send {count} mails at once, the rate limit is {limit} mails per minute"
+                ),
                 HashMap::new(),
-            ))
+            ));
         }
+    }
+
+    // The admin key isn't tied to a single client, so it is limited per address instead.
+    let rate_limit_key = match api_key.id {
+        Some(api_key_id) => format!("key:{api_key_id}"),
+        None => format!("ip:{}", address.ip()),
     };
 
-    match diesel::insert_into(mails::table)
-        .values(&new_mail)
-        .returning(Mail::as_returning())
-        .get_result(&mut conn)
-    {
-        Ok(created_mail) => Ok(created_mail),
-        Err(err) => {
-            tracing::error!("{}", err);
-            Err(ApiError::new(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                ApiErrorCode::Unknown,
-                "Failed to save mail: ".to_string() + &err.to_string(),
-                HashMap::new(),
-            ))
-        }
+    if let Err(wait) = rate_limit.try_acquire(&rate_limit_key, count as u32) {
+        let retry_after = wait.as_secs().max(1);
+
+        return Err(ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            ApiErrorCode::RateLimited,
+            format!("Rate limit exceeded, try again in {retry_after} seconds"),
+            HashMap::from([("retry_after".to_string(), retry_after.to_string())]),
+        ));
     }
+
+    Ok(())
 }
 
 pub async fn send_mails(
     pool: Extension<Arc<database::ConnectionPool>>,
+    Extension(api_key): Extension<AuthenticatedKey>,
+    Extension(rate_limit): Extension<Arc<SendRateLimit>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
     Json(payload): Json<Vec<SendMailRequest>>,
 ) -> Result<Json<Vec<Result<SendMailResponse, ApiError>>>, ApiError> {
-    let mut mails: Vec<Result<Mail, ApiError>> = vec![];
+    acquire_send_rate_limit(&rate_limit, &api_key, address, payload.len())?;
+
+    let mut mails: Vec<Result<StoredMail, ApiError>> = vec![];
 
     for mail_payload in payload {
-        let created_mail = send_mail(pool.clone(), mail_payload).await;
+        let created_mail = send_mail(pool.clone(), &api_key, mail_payload).await;
         mails.push(created_mail);
     }
 
     Ok(Json(
         mails
             .into_iter()
-            .map(|mail| match mail {
-                Ok(mail) => Ok(SendMailResponse::new(mail)),
-                Err(err) => Err(err),
-            })
+            .map(|mail| mail.map(SendMailResponse::new))
             .collect(),
     ))
 }
 
+/// Load the attachments and recipients of a mail.
+fn load_stored_mail(conn: &mut PgConnection, mail: Mail) -> Result<StoredMail, ApiError> {
+    use crate::database::schema::mail_attachments;
+
+    let attachments = mail_attachments::table
+        .filter(mail_attachments::mail_id.eq(mail.id))
+        .load::<MailAttachment>(conn)
+        .map_err(|err| database_error("Failed to load attachments", err))?;
+
+    let recipients = load_recipients(conn, &[mail.id])
+        .map_err(|err| database_error("Failed to load recipients", err))?
+        .remove(&mail.id)
+        .unwrap_or_default();
+
+    Ok(StoredMail {
+        mail,
+        attachments,
+        recipients,
+    })
+}
+
 pub async fn get_mail_status(
     pool: Extension<Arc<database::ConnectionPool>>,
     Path(mail_id): Path<i32>,
 ) -> Result<Json<SendMailResponse>, ApiError> {
     use crate::database::schema::mails;
 
-    let mut conn = match pool.get() {
-        Ok(conn) => conn,
+    let mut conn = get_connection(&pool)?;
+
+    let mail = match mails::table.find(mail_id).first::<Mail>(&mut conn) {
+        Ok(mail) => mail,
         Err(err) => {
             return Err(ApiError::new(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                ApiErrorCode::Unknown,
-                "Could not connect to database: ".to_string() + &err.to_string(),
+                StatusCode::NOT_FOUND,
+                ApiErrorCode::NotFound,
+                "Mail not found: ".to_string() + &err.to_string(),
                 HashMap::new(),
             ))
         }
     };
 
-    let mail = match mails::table.find(mail_id).first::<Mail>(&mut conn) {
+    Ok(Json(SendMailResponse::new(load_stored_mail(
+        &mut conn, mail,
+    )?)))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMailRequest {
+    pub recipient: Option<String>,
+    pub subject: Option<String>,
+    pub priority: Option<i32>,
+    pub schedule_at: Option<String>,
+}
+
+/// Replace the first `to` address of a mail, which is the one stored as its `recipient`.
+fn replace_main_recipient(
+    conn: &mut PgConnection,
+    mail_id: i32,
+    recipient: &str,
+) -> Result<(), diesel::result::Error> {
+    use crate::database::schema::mail_recipients;
+
+    let main_recipient_id = mail_recipients::table
+        .select(mail_recipients::id)
+        .filter(mail_recipients::mail_id.eq(mail_id))
+        .filter(mail_recipients::kind.eq(RecipientKind::To.as_str()))
+        .order(mail_recipients::id.asc())
+        .first::<i32>(conn)
+        .optional()?;
+
+    match main_recipient_id {
+        Some(main_recipient_id) => {
+            diesel::update(mail_recipients::table.find(main_recipient_id))
+                .set((
+                    mail_recipients::address.eq(recipient),
+                    mail_recipients::normalized_address
+                        .eq(unsubscribe::normalize_address(recipient)),
+                ))
+                .execute(conn)?;
+        }
+        None => {
+            insert_recipients(conn, mail_id, &[(RecipientKind::To, recipient.to_string())])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reject changing the recipient of a mail with an unsubscribe link, the link and
+/// `List-Unsubscribe` header were signed for the old address. The template data isn't stored, so
+/// the mail can't be rendered again for the new one.
+fn check_recipient_changeable(conn: &mut PgConnection, mail_id: i32) -> Result<(), ApiError> {
+    use crate::database::schema::mails;
+
+    let mail = match mails::table.find(mail_id).first::<Mail>(conn) {
         Ok(mail) => mail,
         Err(err) => {
             return Err(ApiError::new(
@@ -232,7 +1093,230 @@ pub async fn get_mail_status(
         }
     };
 
-    Ok(Json(SendMailResponse::new(mail)))
+    if [&mail.html_body, &mail.text_body, &mail.headers.to_string()]
+        .iter()
+        .any(|text| unsubscribe::contains_unsubscribe_url(text))
+    {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            ApiErrorCode::Conflict,
+            format!(
+                "The recipient of mail {mail_id} can't be changed, it contains an unsubscribe link \
+                for the current recipient. Cancel it and send a new mail instead"
+            ),
+            HashMap::new(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Apply `changes` to a mail, but only while it is still waiting to be sent.
+///
+/// The status check is part of the update itself, so a scheduler run can't claim the mail in
+/// between checking and updating it.
+fn update_pending_mail(
+    conn: &mut PgConnection,
+    mail_id: i32,
+    changes: &UpdateMail,
+    event_type: MailEventType,
+) -> Result<Mail, ApiError> {
+    use crate::database::schema::mails;
+
+    let now = SystemTime::now();
+
+    let result = conn.transaction(|conn| {
+        let updated_mail = diesel::update(
+            mails::table
+                .filter(mails::id.eq(mail_id))
+                .filter(
+                    mails::status
+                        .eq_any([MailStatus::Queued.as_str(), MailStatus::Retrying.as_str()]),
+                )
+                .filter(
+                    mails::locked_until
+                        .is_null()
+                        .or(mails::locked_until.lt(now)),
+                ),
+        )
+        .set(changes)
+        .returning(Mail::as_returning())
+        .get_result(conn)
+        .optional()?;
+
+        if updated_mail.is_some() {
+            if let Some(recipient) = changes.recipient {
+                replace_main_recipient(conn, mail_id, recipient)?;
+            }
+
+            record_event(conn, mail_id, event_type, None, None)?;
+        }
+
+        Ok::<Option<Mail>, diesel::result::Error>(updated_mail)
+    });
+
+    match result {
+        Ok(Some(mail)) => Ok(mail),
+        Ok(None) => {
+            // Nothing was updated, find out whether the mail doesn't exist or can't be changed.
+            match mails::table.find(mail_id).first::<Mail>(conn) {
+                Ok(mail) => Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    ApiErrorCode::Conflict,
+                    format!(
+                        "Mail {mail_id} can no longer be changed, it is {}",
+                        mail.status
+                    ),
+                    HashMap::new(),
+                )),
+                Err(err) => Err(ApiError::new(
+                    StatusCode::NOT_FOUND,
+                    ApiErrorCode::NotFound,
+                    "Mail not found: ".to_string() + &err.to_string(),
+                    HashMap::new(),
+                )),
+            }
+        }
+        Err(err) => Err(database_error("Failed to update mail", err)),
+    }
+}
+
+pub async fn update_mail(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path(mail_id): Path<i32>,
+    Json(payload): Json<UpdateMailRequest>,
+) -> Result<Json<SendMailResponse>, ApiError> {
+    if payload.recipient.is_none()
+        && payload.subject.is_none()
+        && payload.priority.is_none()
+        && payload.schedule_at.is_none()
+    {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            ApiErrorCode::ValidationError,
+            "Nothing to update, pass `recipient`, `subject`, `priority` and/or `schedule_at`"
+                .to_string(),
+            HashMap::new(),
+        ));
+    }
+
+    if let Some(subject) = &payload.subject {
+        validate_subject(subject)?;
+    }
+
+    if let Some(recipient) = &payload.recipient {
+        let mut errors = HashMap::new();
+        validate_address("recipient", recipient, &mut errors);
+
+        if !errors.is_empty() {
+            return Err(validation_error(errors));
+        }
+    }
+
+    let scheduled_at = match &payload.schedule_at {
+        Some(iso_string) => Some(parse_schedule_at(iso_string)?),
+        None => None,
+    };
+
+    let changes = UpdateMail {
+        recipient: payload.recipient.as_deref(),
+        subject: payload.subject.as_deref(),
+        priority: payload.priority,
+        scheduled_at,
+        // An explicit new schedule replaces any pending retry delay.
+        next_attempt_at: scheduled_at.map(|_| None),
+        status: None,
+    };
+
+    let mut conn = get_connection(&pool)?;
+
+    if let Some(recipient) = &payload.recipient {
+        check_suppressions(
+            &mut conn,
+            vec![(
+                "recipient".to_string(),
+                RecipientKind::To,
+                recipient.clone(),
+            )],
+        )?;
+        check_recipient_changeable(&mut conn, mail_id)?;
+    }
+
+    let mail = update_pending_mail(&mut conn, mail_id, &changes, MailEventType::Updated)?;
+    Ok(Json(SendMailResponse::new(load_stored_mail(
+        &mut conn, mail,
+    )?)))
+}
+
+pub async fn cancel_mail(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path(mail_id): Path<i32>,
+) -> Result<Json<SendMailResponse>, ApiError> {
+    let changes = UpdateMail {
+        status: Some(MailStatus::Cancelled.as_str()),
+        ..Default::default()
+    };
+
+    let mut conn = get_connection(&pool)?;
+
+    let mail = update_pending_mail(&mut conn, mail_id, &changes, MailEventType::Cancelled)?;
+    Ok(Json(SendMailResponse::new(load_stored_mail(
+        &mut conn, mail,
+    )?)))
+}
+
+#[derive(Serialize)]
+pub struct MailEventResponse {
+    id: i32,
+    event_type: String,
+    smtp_code: Option<i32>,
+    message: Option<String>,
+    created_at: String,
+}
+
+impl MailEventResponse {
+    fn new(event: MailEvent) -> Self {
+        Self {
+            id: event.id,
+            event_type: event.event_type,
+            smtp_code: event.smtp_code,
+            message: event.message,
+            created_at: meel_utils::time::system_time_to_iso_string(event.created_at),
+        }
+    }
+}
+
+pub async fn get_mail_events(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path(mail_id): Path<i32>,
+) -> Result<Json<Vec<MailEventResponse>>, ApiError> {
+    use crate::database::schema::{mail_events, mails};
+
+    let mut conn = get_connection(&pool)?;
+
+    if let Err(err) = mails::table
+        .find(mail_id)
+        .select(mails::id)
+        .first::<i32>(&mut conn)
+    {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            ApiErrorCode::NotFound,
+            "Mail not found: ".to_string() + &err.to_string(),
+            HashMap::new(),
+        ));
+    }
+
+    match mail_events::table
+        .filter(mail_events::mail_id.eq(mail_id))
+        .order((mail_events::created_at.asc(), mail_events::id.asc()))
+        .load::<MailEvent>(&mut conn)
+    {
+        Ok(events) => Ok(Json(
+            events.into_iter().map(MailEventResponse::new).collect(),
+        )),
+        Err(err) => Err(database_error("Failed to load mail events", err)),
+    }
 }
 
 pub async fn get_mail_body(
@@ -241,17 +1325,7 @@ pub async fn get_mail_body(
 ) -> Result<Html<String>, ApiError> {
     use crate::database::schema::mails;
 
-    let mut conn = match pool.get() {
-        Ok(conn) => conn,
-        Err(err) => {
-            return Err(ApiError::new(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                ApiErrorCode::Unknown,
-                "Could not connect to database: ".to_string() + &err.to_string(),
-                HashMap::new(),
-            ))
-        }
-    };
+    let mut conn = get_connection(&pool)?;
 
     let mail = match mails::table.find(mail_id).first::<Mail>(&mut conn) {
         Ok(mail) => mail,
@@ -267,3 +1341,330 @@ pub async fn get_mail_body(
 
     Ok(Html(mail.html_body))
 }
+
+#[derive(Deserialize)]
+pub struct ListMailsQuery {
+    pub sender: Option<String>,
+    pub recipient: Option<String>,
+    /// One or more statuses, separated by commas.
+    pub status: Option<String>,
+    pub priority: Option<i32>,
+    pub template: Option<String>,
+    /// One or more tags, separated by commas. Mails need to have all of them.
+    pub tag: Option<String>,
+    /// A `key:value` pair the metadata of mails needs to contain.
+    pub metadata: Option<String>,
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    pub scheduled_after: Option<String>,
+    pub scheduled_before: Option<String>,
+    pub sent_after: Option<String>,
+    pub sent_before: Option<String>,
+    /// Either `created_at` (default) or `scheduled_at`.
+    pub sort: Option<String>,
+    /// Either `desc` (default) or `asc`.
+    pub order: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ListMailsResponse {
+    mails: Vec<SendMailResponse>,
+    /// Pass this as `cursor` to fetch the next page, `None` on the last page.
+    next_cursor: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MailSortField {
+    CreatedAt,
+    ScheduledAt,
+}
+
+fn invalid_query_parameter(name: &str, message: String) -> ApiError {
+    ApiError::new(
+        StatusCode::BAD_REQUEST,
+        ApiErrorCode::ValidationError,
+        format!("Invalid `{name}`: {message}"),
+        HashMap::new(),
+    )
+}
+
+fn parse_time_parameter(
+    name: &str,
+    value: &Option<String>,
+) -> Result<Option<SystemTime>, ApiError> {
+    match value {
+        Some(iso_string) => meel_utils::time::iso_string_to_system_time(iso_string)
+            .map(Some)
+            .map_err(|err| invalid_query_parameter(name, err.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Cursors point at the last mail of the previous page, as `<sort value in µs>:<id>`.
+fn encode_cursor(sort_value: SystemTime, mail_id: i32) -> String {
+    let micros = sort_value
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros();
+
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(format!("{micros}:{mail_id}"))
+}
+
+fn decode_cursor(cursor: &str) -> Option<(SystemTime, i32)> {
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (micros, mail_id) = decoded.split_once(':')?;
+
+    Some((
+        UNIX_EPOCH + Duration::from_micros(micros.parse().ok()?),
+        mail_id.parse().ok()?,
+    ))
+}
+
+#[test]
+fn test_cursor_round_trip() {
+    let time = UNIX_EPOCH + Duration::from_micros(1_718_000_000_123_456);
+    assert_eq!(decode_cursor(&encode_cursor(time, 42)), Some((time, 42)));
+    assert_eq!(decode_cursor("not a cursor"), None);
+}
+
+/// Apply keyset pagination on `$column`, using the mail id as a tiebreaker.
+macro_rules! paginate_by {
+    ($query:expr, $column:expr, $cursor:expr, $descending:expr) => {{
+        use crate::database::schema::mails;
+
+        let mut query = $query;
+
+        if let Some((value, cursor_id)) = $cursor {
+            query = if $descending {
+                query.filter(
+                    $column
+                        .lt(value)
+                        .or($column.eq(value).and(mails::id.lt(cursor_id))),
+                )
+            } else {
+                query.filter(
+                    $column
+                        .gt(value)
+                        .or($column.eq(value).and(mails::id.gt(cursor_id))),
+                )
+            };
+        }
+
+        if $descending {
+            query.order(($column.desc(), mails::id.desc()))
+        } else {
+            query.order(($column.asc(), mails::id.asc()))
+        }
+    }};
+}
+
+pub async fn list_mails(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Query(params): Query<ListMailsQuery>,
+) -> Result<Json<ListMailsResponse>, ApiError> {
+    use crate::database::schema::{mail_attachments, mail_recipients, mails};
+
+    const DEFAULT_LIMIT: i64 = 50;
+    const MAX_LIMIT: i64 = 500;
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let sort = match params.sort.as_deref() {
+        None | Some("created_at") => MailSortField::CreatedAt,
+        Some("scheduled_at") => MailSortField::ScheduledAt,
+        Some(sort) => {
+            return Err(invalid_query_parameter(
+                "sort",
+                format!("`{sort}` is not one of `created_at`, `scheduled_at`"),
+            ))
+        }
+    };
+
+    let descending = match params.order.as_deref() {
+        None | Some("desc") => true,
+        Some("asc") => false,
+        Some(order) => {
+            return Err(invalid_query_parameter(
+                "order",
+                format!("`{order}` is not one of `asc`, `desc`"),
+            ))
+        }
+    };
+
+    let cursor = match &params.cursor {
+        Some(cursor) => match decode_cursor(cursor) {
+            Some(cursor) => Some(cursor),
+            None => {
+                return Err(invalid_query_parameter(
+                    "cursor",
+                    "malformed cursor".to_string(),
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let statuses = match &params.status {
+        Some(statuses) => Some(
+            statuses
+                .split(',')
+                .map(|status| status.trim().parse::<MailStatus>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| invalid_query_parameter("status", err))?,
+        ),
+        None => None,
+    };
+
+    let metadata = match &params.metadata {
+        Some(metadata) => match metadata.split_once(':') {
+            Some((key, value)) => Some(serde_json::json!({ key: value })),
+            None => {
+                return Err(invalid_query_parameter(
+                    "metadata",
+                    "expected a `key:value` pair".to_string(),
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let created_after = parse_time_parameter("created_after", &params.created_after)?;
+    let created_before = parse_time_parameter("created_before", &params.created_before)?;
+    let scheduled_after = parse_time_parameter("scheduled_after", &params.scheduled_after)?;
+    let scheduled_before = parse_time_parameter("scheduled_before", &params.scheduled_before)?;
+    let sent_after = parse_time_parameter("sent_after", &params.sent_after)?;
+    let sent_before = parse_time_parameter("sent_before", &params.sent_before)?;
+
+    let mut query = mails::table.into_boxed();
+
+    if let Some(sender) = &params.sender {
+        query = query.filter(mails::sender.eq(sender.clone()));
+    }
+
+    if let Some(recipient) = &params.recipient {
+        // Match any of the to, cc and bcc recipients, regardless of display name and case.
+        query = query.filter(
+            mails::id.eq_any(
+                mail_recipients::table
+                    .select(mail_recipients::mail_id)
+                    .filter(
+                        mail_recipients::normalized_address
+                            .eq(unsubscribe::normalize_address(recipient)),
+                    ),
+            ),
+        );
+    }
+
+    if let Some(statuses) = statuses {
+        query = query.filter(
+            mails::status.eq_any(statuses.iter().map(MailStatus::as_str).collect::<Vec<_>>()),
+        );
+    }
+
+    if let Some(priority) = params.priority {
+        query = query.filter(mails::priority.eq(priority));
+    }
+
+    if let Some(template) = &params.template {
+        query = query.filter(mails::template.eq(template.clone()));
+    }
+
+    if let Some(tags) = &params.tag {
+        let tags: Vec<String> = tags
+            .split(',')
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        query = query.filter(mails::tags.contains(tags));
+    }
+
+    if let Some(metadata) = metadata {
+        query = query.filter(mails::metadata.contains(metadata));
+    }
+
+    if let Some(created_after) = created_after {
+        query = query.filter(mails::created_at.ge(created_after));
+    }
+    if let Some(created_before) = created_before {
+        query = query.filter(mails::created_at.lt(created_before));
+    }
+    if let Some(scheduled_after) = scheduled_after {
+        query = query.filter(mails::scheduled_at.ge(scheduled_after));
+    }
+    if let Some(scheduled_before) = scheduled_before {
+        query = query.filter(mails::scheduled_at.lt(scheduled_before));
+    }
+    if let Some(sent_after) = sent_after {
+        query = query.filter(mails::sent_at.ge(sent_after));
+    }
+    if let Some(sent_before) = sent_before {
+        query = query.filter(mails::sent_at.lt(sent_before));
+    }
+
+    let query = match sort {
+        MailSortField::CreatedAt => paginate_by!(query, mails::created_at, cursor, descending),
+        MailSortField::ScheduledAt => paginate_by!(query, mails::scheduled_at, cursor, descending),
+    };
+
+    let mut conn = get_connection(&pool)?;
+
+    // Fetch one extra mail, to know whether there is a next page.
+    let mut found_mails = match query.limit(limit + 1).load::<Mail>(&mut conn) {
+        Ok(found_mails) => found_mails,
+        Err(err) => return Err(database_error("Failed to load mails", err)),
+    };
+
+    let next_cursor = if found_mails.len() as i64 > limit {
+        found_mails.truncate(limit as usize);
+        found_mails.last().map(|mail| {
+            let sort_value = match sort {
+                MailSortField::CreatedAt => mail.created_at,
+                MailSortField::ScheduledAt => mail.scheduled_at,
+            };
+            encode_cursor(sort_value, mail.id)
+        })
+    } else {
+        None
+    };
+
+    let mail_ids: Vec<i32> = found_mails.iter().map(|mail| mail.id).collect();
+    let mut attachments_by_mail: HashMap<i32, Vec<MailAttachment>> = HashMap::new();
+
+    match mail_attachments::table
+        .filter(mail_attachments::mail_id.eq_any(&mail_ids))
+        .load::<MailAttachment>(&mut conn)
+    {
+        Ok(attachments) => {
+            for attachment in attachments {
+                attachments_by_mail
+                    .entry(attachment.mail_id)
+                    .or_default()
+                    .push(attachment);
+            }
+        }
+        Err(err) => return Err(database_error("Failed to load attachments", err)),
+    };
+
+    let mut recipients_by_mail = match load_recipients(&mut conn, &mail_ids) {
+        Ok(recipients_by_mail) => recipients_by_mail,
+        Err(err) => return Err(database_error("Failed to load recipients", err)),
+    };
+
+    Ok(Json(ListMailsResponse {
+        mails: found_mails
+            .into_iter()
+            .map(|mail| {
+                SendMailResponse::new(StoredMail {
+                    attachments: attachments_by_mail.remove(&mail.id).unwrap_or_default(),
+                    recipients: recipients_by_mail.remove(&mail.id).unwrap_or_default(),
+                    mail,
+                })
+            })
+            .collect(),
+        next_cursor,
+    }))
+}
@@ -1,2 +1,39 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum::Extension;
+use meel_utils::api_error::{ApiError, ApiErrorCode};
+
+use crate::database;
+
+pub mod api_keys;
+pub mod extract;
+pub mod mailing_lists;
 pub mod mails;
+pub mod suppressions;
 pub mod templates;
+pub mod unsubscribe;
+
+pub fn get_connection(
+    pool: &Extension<Arc<database::ConnectionPool>>,
+) -> Result<database::PooledConnection, ApiError> {
+    pool.get().map_err(|err| {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ApiErrorCode::DatabaseError,
+            "Could not connect to database: ".to_string() + &err.to_string(),
+            HashMap::new(),
+        )
+    })
+}
+
+pub fn database_error(message: &str, err: diesel::result::Error) -> ApiError {
+    tracing::error!("{}", err);
+    ApiError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        ApiErrorCode::DatabaseError,
+        format!("{message}: {err}"),
+        HashMap::new(),
+    )
+}
@@ -0,0 +1,105 @@
+use crate::database;
+use crate::database::models::Suppression;
+use crate::routes::extract::{Json, Path, Query};
+use crate::routes::mailing_lists::PaginationQuery;
+use crate::routes::{database_error, get_connection};
+use axum::http::StatusCode;
+use axum::Extension;
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
+use meel_utils::api_error::{ApiError, ApiErrorCode};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Serialize)]
+pub struct SuppressionResponse {
+    id: i32,
+    email: String,
+    reason: String,
+    mailing_list_id: Option<i32>,
+    created_at: String,
+}
+
+impl SuppressionResponse {
+    fn new(suppression: Suppression) -> Self {
+        Self {
+            id: suppression.id,
+            email: suppression.email,
+            reason: suppression.reason,
+            mailing_list_id: suppression.mailing_list_id,
+            created_at: meel_utils::time::system_time_to_iso_string(suppression.created_at),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SuppressionsPageResponse {
+    suppressions: Vec<SuppressionResponse>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+pub async fn get_suppressions(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Query(pagination): Query<PaginationQuery>,
+) -> Result<Json<SuppressionsPageResponse>, ApiError> {
+    use crate::database::schema::suppressions;
+
+    const DEFAULT_PER_PAGE: i64 = 50;
+    const MAX_PER_PAGE: i64 = 500;
+
+    let page = pagination.page.unwrap_or(1).max(1);
+    let per_page = pagination
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+
+    let mut conn = get_connection(&pool)?;
+
+    let total = suppressions::table
+        .count()
+        .get_result::<i64>(&mut conn)
+        .map_err(|err| database_error("Failed to count suppressions", err))?;
+
+    let found_suppressions = suppressions::table
+        .order(suppressions::id.desc())
+        .limit(per_page)
+        .offset((page - 1) * per_page)
+        .select(Suppression::as_select())
+        .load(&mut conn)
+        .map_err(|err| database_error("Failed to load suppressions", err))?;
+
+    Ok(Json(SuppressionsPageResponse {
+        suppressions: found_suppressions
+            .into_iter()
+            .map(SuppressionResponse::new)
+            .collect(),
+        page,
+        per_page,
+        total,
+    }))
+}
+
+/// Remove an address from the suppression list, so it can receive mail again.
+pub async fn remove_suppression(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path(suppression_id): Path<i32>,
+) -> Result<StatusCode, ApiError> {
+    use crate::database::schema::suppressions;
+
+    let mut conn = get_connection(&pool)?;
+
+    match diesel::delete(suppressions::table.filter(suppressions::id.eq(suppression_id)))
+        .execute(&mut conn)
+    {
+        Ok(0) => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            ApiErrorCode::NotFound,
+            format!("Suppression {suppression_id} not found"),
+            HashMap::new(),
+        )),
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(database_error("Failed to remove suppression", err)),
+    }
+}
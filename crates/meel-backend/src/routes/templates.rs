@@ -1,19 +1,73 @@
 use std::collections::HashMap;
 
-use axum::extract::Path;
+use crate::routes::extract::{Json, Path, Query};
 use axum::http::StatusCode;
 use axum::response::Html;
-use axum::Json;
+use meel_templating::templating::{TemplateDataMap, TemplateError};
+use meel_templating::{locales, plain_text, templating};
 use meel_utils::api_error::{ApiError, ApiErrorCode};
 use serde::{Deserialize, Serialize};
-use meel_templating::templating;
-use meel_templating::templating::TemplateDataMap;
+
+/// Map a templating error to the API error returned to the client.
+pub fn template_error(err: TemplateError) -> ApiError {
+    let (status_code, error_code) = match &err {
+        TemplateError::InvalidName(_) => (StatusCode::BAD_REQUEST, ApiErrorCode::ValidationError),
+        TemplateError::NotFound(_) => (StatusCode::NOT_FOUND, ApiErrorCode::TemplateNotFound),
+        TemplateError::Read(_) => (StatusCode::INTERNAL_SERVER_ERROR, ApiErrorCode::Unknown),
+        TemplateError::Render(_) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            ApiErrorCode::TemplateRenderError,
+        ),
+        TemplateError::MissingVariables(_) => {
+            (StatusCode::BAD_REQUEST, ApiErrorCode::ValidationError)
+        }
+    };
+
+    let details = match &err {
+        TemplateError::MissingVariables(variables) => variables
+            .iter()
+            .map(|variable| {
+                (
+                    format!("data.{variable}"),
+                    "Required by the template".to_string(),
+                )
+            })
+            .collect(),
+        _ => HashMap::new(),
+    };
+
+    if error_code == ApiErrorCode::Unknown {
+        tracing::error!("{}", err);
+    }
+
+    ApiError::new(
+        status_code,
+        error_code,
+        "Could not render template: ".to_string() + &err.to_string(),
+        details,
+    )
+}
 
 #[derive(Serialize)]
 pub struct Template {
     name: String,
 }
 
+/// Whether a template is the `<name>.<locale>` translation of another template, rather than a
+/// template of its own.
+fn is_locale_variant(name: &str) -> bool {
+    name.rsplit_once('.')
+        .is_some_and(|(_, locale)| locales::is_valid_locale(locale))
+}
+
+#[test]
+fn test_is_locale_variant() {
+    assert!(is_locale_variant("welcome.nl"));
+    assert!(is_locale_variant("welcome.pt-BR"));
+    assert!(!is_locale_variant("welcome"));
+    assert!(!is_locale_variant("welcome.v2"));
+}
+
 pub async fn get_templates() -> Result<Json<Vec<Template>>, ApiError> {
     let entries = match glob::glob(&format!(
         "{}/**/*.mustache",
@@ -35,7 +89,9 @@ pub async fn get_templates() -> Result<Json<Vec<Template>>, ApiError> {
         match entry {
             Ok(path) => {
                 let name = path.file_stem().unwrap().to_str().unwrap().to_string();
-                templates.push(Template { name });
+                if !is_locale_variant(&name) {
+                    templates.push(Template { name });
+                }
             }
             Err(_) => {
                 return Err(ApiError::new(
@@ -56,6 +112,12 @@ pub struct RenderTemplateRequest {
     data: TemplateDataMap,
     allow_html: Option<bool>,
     minify_html: Option<bool>,
+    locale: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RenderPlainTextQuery {
+    locale: Option<String>,
 }
 
 pub async fn render_template(
@@ -67,34 +129,28 @@ pub async fn render_template(
         data.data,
         data.allow_html.unwrap_or(false),
         data.minify_html.unwrap_or(true),
+        data.locale.as_deref(),
     ) {
-        Ok(html) => Ok(Html(html)),
-        Err(err) => {
-            tracing::error!("{}", err);
-            Err(ApiError::new(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                ApiErrorCode::Unknown,
-                "Could not render template: ".to_string() + &err.to_string(),
-                HashMap::new(),
-            ))
-        }
+        Ok(rendered) => Ok(Html(rendered.html)),
+        Err(err) => Err(template_error(err)),
     }
 }
 
 pub async fn render_template_plain_text(
     Path(template_name): Path<String>,
+    Query(query): Query<RenderPlainTextQuery>,
     Json(data): Json<TemplateDataMap>,
 ) -> Result<String, ApiError> {
-    match templating::render_plain_text(template_name, data) {
-        Ok(html) => Ok(html),
-        Err(err) => {
-            tracing::error!("{}", err);
-            Err(ApiError::new(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                ApiErrorCode::Unknown,
-                "Could not render template: ".to_string() + &err.to_string(),
-                HashMap::new(),
-            ))
+    let locale = query.locale.as_deref();
+
+    match templating::render_plain_text(template_name.clone(), data.clone(), locale) {
+        Ok(text) => Ok(text),
+        // Preview the text that is generated for mails without a plain text template.
+        Err(TemplateError::NotFound(_)) => {
+            templating::render(template_name, data, false, false, locale)
+                .map(|rendered| plain_text::html_to_text(&rendered.html))
+                .map_err(template_error)
         }
+        Err(err) => Err(template_error(err)),
     }
 }
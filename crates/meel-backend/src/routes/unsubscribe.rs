@@ -0,0 +1,79 @@
+use crate::database;
+use crate::routes::extract::Path;
+use crate::routes::{database_error, get_connection};
+use crate::unsubscribe::{parse_unsubscribe_token, suppress, REASON_UNSUBSCRIBED};
+use axum::http::StatusCode;
+use axum::response::Html;
+use axum::Extension;
+use diesel::{OptionalExtension, QueryDsl, RunQueryDsl};
+use meel_utils::api_error::{ApiError, ApiErrorCode};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn invalid_token() -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        ApiErrorCode::NotFound,
+        "Invalid or expired unsubscribe link".to_string(),
+        HashMap::new(),
+    )
+}
+
+fn render_page(title: &str, body: &str) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><meta name=\"viewport\" \
+        content=\"width=device-width, initial-scale=1\"><title>{title}</title></head>\
+        <body style=\"font-family: sans-serif; max-width: 32rem; margin: 4rem auto; padding: 0 1rem;\">\
+        <h1>{title}</h1>{body}</body></html>"
+    ))
+}
+
+/// Ask for confirmation before unsubscribing. Mail scanners follow links in mails, so a `GET`
+/// request must not unsubscribe by itself.
+pub async fn get_unsubscribe_page(Path(token): Path<String>) -> Result<Html<String>, ApiError> {
+    if parse_unsubscribe_token(&token).is_none() {
+        return Err(invalid_token());
+    }
+
+    Ok(render_page(
+        "Unsubscribe",
+        "<p>Click the button below to stop receiving these emails.</p>\
+        <form method=\"post\"><button type=\"submit\">Unsubscribe</button></form>",
+    ))
+}
+
+/// Unsubscribe the address in the token. This is also the target of one-click unsubscribe
+/// (RFC 8058), which posts `List-Unsubscribe=One-Click` to the URL from the mail header.
+pub async fn unsubscribe(
+    pool: Extension<Arc<database::ConnectionPool>>,
+    Path(token): Path<String>,
+) -> Result<Html<String>, ApiError> {
+    use crate::database::schema::mailing_lists;
+
+    let Some((mailing_list_id, email)) = parse_unsubscribe_token(&token) else {
+        return Err(invalid_token());
+    };
+
+    let mut conn = get_connection(&pool)?;
+
+    // The list may have been deleted since the mail was sent, the address is suppressed anyway.
+    let mailing_list_id = match mailing_list_id {
+        Some(mailing_list_id) => mailing_lists::table
+            .find(mailing_list_id)
+            .select(mailing_lists::id)
+            .first::<i32>(&mut conn)
+            .optional()
+            .map_err(|err| database_error("Failed to load mailing list", err))?,
+        None => None,
+    };
+
+    suppress(&mut conn, &email, REASON_UNSUBSCRIBED, mailing_list_id)
+        .map_err(|err| database_error("Failed to unsubscribe", err))?;
+
+    tracing::info!("Unsubscribed {}", email);
+
+    Ok(render_page(
+        "Unsubscribed",
+        "<p>You will no longer receive these emails.</p>",
+    ))
+}
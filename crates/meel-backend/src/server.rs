@@ -1,28 +1,124 @@
 use std::sync::Arc;
 
-use axum::routing::{get, post};
+use axum::extract::DefaultBodyLimit;
+use axum::middleware::from_fn_with_state;
+use axum::routing::{delete, get, patch, post};
 use axum::{Extension, Router};
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
 
+use crate::auth;
+use crate::auth::ApiKeyScope;
 use crate::database::ConnectionPool;
-use crate::routes::mails::{get_mail_body, get_mail_status, send_mails};
+use crate::rate_limiter::SendRateLimit;
+use crate::routes::api_keys::{create_api_key, get_api_keys, revoke_api_key};
+use crate::routes::mailing_lists::{
+    add_subscriber, create_mailing_list, delete_mailing_list, get_mailing_list, get_mailing_lists,
+    get_subscribers, remove_subscriber, send_to_mailing_list, update_mailing_list,
+};
+use crate::routes::mails::{
+    cancel_mail, get_mail_body, get_mail_events, get_mail_status, list_mails, send_mails,
+    update_mail,
+};
+use crate::routes::suppressions::{get_suppressions, remove_suppression};
 use crate::routes::templates::{get_templates, render_template, render_template_plain_text};
+use crate::routes::unsubscribe::{get_unsubscribe_page, unsubscribe};
+use crate::unsubscribe::check_unsubscribe_secret;
 
 pub async fn create(shared_pool: Arc<ConnectionPool>) -> Router {
     let cors_layer = CorsLayer::permissive();
 
-    Router::new()
+    // Attachments are sent as base64 in the request body, so we need more than axum's default.
+    const DEFAULT_MAX_REQUEST_SIZE: usize = 25 * 1024 * 1024;
+    let max_request_size = meel_utils::env::get_var(
+        "MEEL_MAX_REQUEST_SIZE",
+        Some(&DEFAULT_MAX_REQUEST_SIZE.to_string()),
+    )
+    .unwrap()
+    .parse::<usize>()
+    .unwrap_or(DEFAULT_MAX_REQUEST_SIZE);
+
+    auth::check_admin_api_key();
+    check_unsubscribe_secret();
+
+    // Linked from the mails themselves, so these can't require an API key.
+    let public_routes = Router::new().route(
+        "/unsubscribe/{token}",
+        get(get_unsubscribe_page).post(unsubscribe),
+    );
+
+    let send_routes = Router::new()
         .route("/mails/send", post(send_mails))
+        .route("/mails/{mail_id}", patch(update_mail).delete(cancel_mail))
+        .route(
+            "/mailing-lists/{mailing_list_id}/send",
+            post(send_to_mailing_list),
+        )
+        .route_layer(from_fn_with_state(ApiKeyScope::Send, auth::require_scope));
+
+    let read_mail_routes = Router::new()
+        .route("/mails", get(list_mails))
         .route("/mails/{mail_id}", get(get_mail_status))
         .route("/mails/{mail_id}/body", get(get_mail_body))
+        .route("/mails/{mail_id}/events", get(get_mail_events))
+        .route_layer(from_fn_with_state(
+            ApiKeyScope::ReadMail,
+            auth::require_scope,
+        ));
+
+    let manage_lists_routes = Router::new()
+        .route(
+            "/mailing-lists",
+            get(get_mailing_lists).post(create_mailing_list),
+        )
+        .route(
+            "/mailing-lists/{mailing_list_id}",
+            get(get_mailing_list)
+                .patch(update_mailing_list)
+                .delete(delete_mailing_list),
+        )
+        .route(
+            "/mailing-lists/{mailing_list_id}/subscribers",
+            get(get_subscribers).post(add_subscriber),
+        )
+        .route(
+            "/mailing-lists/{mailing_list_id}/subscribers/{subscriber_id}",
+            delete(remove_subscriber),
+        )
+        .route("/suppressions", get(get_suppressions))
+        .route("/suppressions/{suppression_id}", delete(remove_suppression))
+        .route_layer(from_fn_with_state(
+            ApiKeyScope::ManageLists,
+            auth::require_scope,
+        ));
+
+    let manage_templates_routes = Router::new()
         .route("/templates", get(get_templates))
         .route("/templates/{template_name}/render", post(render_template))
         .route(
             "/templates/{template_name}/render/plain-text",
             post(render_template_plain_text),
         )
+        .route_layer(from_fn_with_state(
+            ApiKeyScope::ManageTemplates,
+            auth::require_scope,
+        ));
+
+    let admin_routes = Router::new()
+        .route("/api-keys", get(get_api_keys).post(create_api_key))
+        .route("/api-keys/{api_key_id}", delete(revoke_api_key))
+        .route_layer(from_fn_with_state(ApiKeyScope::Admin, auth::require_scope));
+
+    Router::new()
+        .merge(public_routes)
+        .merge(send_routes)
+        .merge(read_mail_routes)
+        .merge(manage_lists_routes)
+        .merge(manage_templates_routes)
+        .merge(admin_routes)
+        .layer(DefaultBodyLimit::max(max_request_size))
         .layer(cors_layer)
         .layer(TraceLayer::new_for_http())
         .layer(Extension(shared_pool))
+        .layer(Extension(Arc::new(SendRateLimit::from_env())))
 }
@@ -0,0 +1,181 @@
+use base64::Engine;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl};
+use hmac::{Hmac, Mac};
+use lettre::message::Mailbox;
+use sha2::Sha256;
+
+use crate::database::models::NewSuppression;
+use crate::database::schema::suppressions;
+
+/// Why an address was suppressed.
+pub const REASON_UNSUBSCRIBED: &str = "unsubscribed";
+
+fn get_unsubscribe_secret() -> Option<String> {
+    meel_utils::env::get_var("MEEL_UNSUBSCRIBE_SECRET", None)
+        .filter(|secret| !secret.trim().is_empty())
+}
+
+fn get_public_url() -> Option<String> {
+    meel_utils::env::get_var("MEEL_PUBLIC_URL", None).filter(|url| !url.trim().is_empty())
+}
+
+/// Warn at startup when unsubscribe links can't be generated.
+pub fn check_unsubscribe_secret() {
+    if get_unsubscribe_secret().is_none() {
+        tracing::warn!("MEEL_UNSUBSCRIBE_SECRET is not set, mails won't have an unsubscribe link");
+    } else if get_public_url().is_none() {
+        tracing::warn!("MEEL_PUBLIC_URL is not set, mails won't have an unsubscribe link");
+    }
+}
+
+/// The plain, lowercased email address of a recipient, which is how suppressions are stored.
+pub fn normalize_address(address: &str) -> String {
+    match address.parse::<Mailbox>() {
+        Ok(mailbox) => mailbox.email.to_string().to_lowercase(),
+        Err(_) => address.trim().to_lowercase(),
+    }
+}
+
+fn create_mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    // HMAC accepts keys of any length, so this can't fail.
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(payload.as_bytes());
+    mac
+}
+
+fn sign(secret: &str, payload: &str) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .encode(create_mac(secret, payload).finalize().into_bytes())
+}
+
+#[test]
+fn test_sign() {
+    // Test case 2 from RFC 4231.
+    assert_eq!(
+        sign("Jefe", "what do ya want for nothing?"),
+        "W9zBRr9gdU5qBCQmCJV1x1oAPwidJzmDnexYuWTsOEM"
+    );
+}
+
+/// Create a token to unsubscribe `email`, from a mailing list or from all mail when there is no
+/// list, formatted as `<payload>.<signature>`.
+fn create_token(secret: &str, mailing_list_id: Option<i32>, email: &str) -> String {
+    let mailing_list_id = mailing_list_id.map(|id| id.to_string()).unwrap_or_default();
+    let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .encode(format!("{mailing_list_id}:{}", normalize_address(email)));
+    let signature = sign(secret, &payload);
+
+    format!("{payload}.{signature}")
+}
+
+/// Verify a token, returning the mailing list and email address it was created for.
+fn verify_token(secret: &str, token: &str) -> Option<(Option<i32>, String)> {
+    let (payload, signature) = token.split_once('.')?;
+
+    let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(signature)
+        .ok()?;
+    // Compares in constant time, so the time taken doesn't reveal where the signatures differ.
+    create_mac(secret, payload).verify_slice(&signature).ok()?;
+
+    let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()?;
+    let payload = String::from_utf8(payload).ok()?;
+    let (mailing_list_id, email) = payload.split_once(':')?;
+
+    let mailing_list_id = match mailing_list_id {
+        "" => None,
+        mailing_list_id => Some(mailing_list_id.parse().ok()?),
+    };
+
+    Some((mailing_list_id, email.to_string()))
+}
+
+#[test]
+fn test_unsubscribe_token() {
+    let token = create_token("secret", Some(3), "Boris <Boris@Example.com>");
+    assert_eq!(
+        verify_token("secret", &token),
+        Some((Some(3), "boris@example.com".to_string()))
+    );
+
+    let token = create_token("secret", None, "boris@example.com");
+    assert_eq!(
+        verify_token("secret", &token),
+        Some((None, "boris@example.com".to_string()))
+    );
+
+    // Tokens don't verify with another secret, or when the payload was changed.
+    assert_eq!(verify_token("other secret", &token), None);
+    let (_, signature) = token.split_once('.').unwrap();
+    let forged_payload =
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode("3:boris@example.com");
+    assert_eq!(
+        verify_token("secret", &format!("{forged_payload}.{signature}")),
+        None
+    );
+}
+
+/// Verify an unsubscribe token with the configured secret.
+pub fn parse_unsubscribe_token(token: &str) -> Option<(Option<i32>, String)> {
+    verify_token(&get_unsubscribe_secret()?, token)
+}
+
+fn get_unsubscribe_base_url() -> Option<String> {
+    Some(format!(
+        "{}/unsubscribe/",
+        get_public_url()?.trim().trim_end_matches('/')
+    ))
+}
+
+/// The link a recipient can use to unsubscribe, `None` when no secret or public URL is configured.
+pub fn get_unsubscribe_url(mailing_list_id: Option<i32>, email: &str) -> Option<String> {
+    let secret = get_unsubscribe_secret()?;
+
+    Some(format!(
+        "{}{}",
+        get_unsubscribe_base_url()?,
+        create_token(&secret, mailing_list_id, email)
+    ))
+}
+
+/// Whether the text contains an unsubscribe link, which only works for the address it was
+/// created for.
+pub fn contains_unsubscribe_url(text: &str) -> bool {
+    get_unsubscribe_secret().is_some()
+        && get_unsubscribe_base_url().is_some_and(|base_url| text.contains(&base_url))
+}
+
+/// Add an address to the suppression list. Suppressing an address twice keeps the first record.
+pub fn suppress(
+    conn: &mut PgConnection,
+    email: &str,
+    reason: &str,
+    mailing_list_id: Option<i32>,
+) -> QueryResult<()> {
+    diesel::insert_into(suppressions::table)
+        .values(NewSuppression {
+            email: &normalize_address(email),
+            reason,
+            mailing_list_id,
+        })
+        .on_conflict(suppressions::email)
+        .do_nothing()
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Find which of the given addresses are suppressed, returned in their normalized form.
+pub fn find_suppressed(conn: &mut PgConnection, addresses: &[String]) -> QueryResult<Vec<String>> {
+    let addresses: Vec<String> = addresses
+        .iter()
+        .map(|address| normalize_address(address))
+        .collect();
+
+    suppressions::table
+        .select(suppressions::email)
+        .filter(suppressions::email.eq_any(addresses))
+        .load(conn)
+}
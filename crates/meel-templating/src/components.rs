@@ -0,0 +1,305 @@
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::fs;
+
+use crate::templating::TemplateError;
+
+/// How deep components may include other components, which also stops runaway recursion through
+/// props or slots that doesn't go through the same component name.
+const MAX_COMPONENT_DEPTH: usize = 10;
+
+pub fn get_component_directory() -> String {
+    format!(
+        "{}/components",
+        meel_utils::env::get_var("MEEL_DATA_DIRECTORY", Some("./data")).unwrap()
+    )
+}
+
+fn is_valid_component_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains("..")
+        && !name.starts_with('/')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '/'))
+}
+
+/// Read a component from the component directory, `extension` is either `mustache` or `txt`.
+fn read_component(directory: &str, name: &str, extension: &str) -> Result<String, TemplateError> {
+    if !is_valid_component_name(name) {
+        return Err(TemplateError::InvalidName(format!(
+            "Invalid component name `{name}`"
+        )));
+    }
+
+    let component_path = format!("{directory}/{name}.{extension}");
+
+    fs::read_to_string(component_path)
+        .map_err(|_| TemplateError::NotFound(format!("Component {name} not found")))
+}
+
+/// A `<x-name prop="value">slot</x-name>` tag found in a template.
+struct ComponentTag {
+    name: String,
+    props: HashMap<String, String>,
+    slot: String,
+    /// The byte range of the whole tag, including the closing tag.
+    start: usize,
+    end: usize,
+}
+
+/// Parse the attributes of an opening tag, starting right after the tag name. Returns the props
+/// and the index right after the `>`, and whether the tag closed itself.
+fn parse_props(contents: &str, start: usize) -> Option<(HashMap<String, String>, usize, bool)> {
+    let bytes = contents.as_bytes();
+    let mut props = HashMap::new();
+    let mut index = start;
+
+    loop {
+        while index < bytes.len() && bytes[index].is_ascii_whitespace() {
+            index += 1;
+        }
+
+        match bytes.get(index)? {
+            b'>' => return Some((props, index + 1, false)),
+            b'/' if bytes.get(index + 1) == Some(&b'>') => return Some((props, index + 2, true)),
+            _ => {}
+        }
+
+        let name_start = index;
+        while index < bytes.len()
+            && !bytes[index].is_ascii_whitespace()
+            && !matches!(bytes[index], b'=' | b'>' | b'/')
+        {
+            index += 1;
+        }
+        let name = contents[name_start..index].to_string();
+        if name.is_empty() {
+            return None;
+        }
+
+        if bytes.get(index) != Some(&b'=') {
+            // A bare attribute, like `<x-button disabled>`.
+            props.insert(name, "true".to_string());
+            continue;
+        }
+
+        let quote = *bytes.get(index + 1)?;
+        if quote != b'"' && quote != b'\'' {
+            return None;
+        }
+
+        let value_start = index + 2;
+        let value_end = value_start + contents[value_start..].find(quote as char)?;
+        props.insert(name, contents[value_start..value_end].to_string());
+        index = value_end + 1;
+    }
+}
+
+/// Find the first component tag in the contents, matching nested tags of the same component.
+fn find_component_tag(contents: &str) -> Result<Option<ComponentTag>, TemplateError> {
+    let opening_tag = Regex::new(r"<x-([A-Za-z0-9_/-]+)").unwrap();
+
+    let Some(captures) = opening_tag.captures(contents) else {
+        return Ok(None);
+    };
+    let whole_match = captures.get(0).unwrap();
+    let name = captures[1].to_string();
+
+    let invalid_tag = || TemplateError::Render(format!("Invalid component tag <x-{name}>"));
+
+    let (props, slot_start, self_closing) =
+        parse_props(contents, whole_match.end()).ok_or_else(invalid_tag)?;
+
+    if self_closing {
+        return Ok(Some(ComponentTag {
+            name,
+            props,
+            slot: String::new(),
+            start: whole_match.start(),
+            end: slot_start,
+        }));
+    }
+
+    // Walk the nested opening and closing tags of this component to find the matching one.
+    let nested_tag = Regex::new(&format!(
+        r"<x-{}[\s/>]|</x-{}\s*>",
+        regex::escape(&name),
+        regex::escape(&name)
+    ))
+    .unwrap();
+    let mut open_tags = 1;
+
+    for nested in nested_tag.find_iter(&contents[slot_start..]) {
+        if nested.as_str().starts_with("</") {
+            open_tags -= 1;
+        } else {
+            open_tags += 1;
+        }
+
+        if open_tags == 0 {
+            return Ok(Some(ComponentTag {
+                slot: contents[slot_start..slot_start + nested.start()].to_string(),
+                name,
+                props,
+                start: whole_match.start(),
+                end: slot_start + nested.end(),
+            }));
+        }
+    }
+
+    Err(TemplateError::Render(format!(
+        "Component tag <x-{name}> is never closed"
+    )))
+}
+
+fn expand(
+    directory: &str,
+    contents: &str,
+    extension: &str,
+    stack: &mut Vec<String>,
+) -> Result<String, TemplateError> {
+    let enter = |name: &str, stack: &Vec<String>| {
+        if stack.iter().any(|parent| parent == name) {
+            return Err(TemplateError::Render(format!(
+                "Component {name} includes itself"
+            )));
+        }
+        if stack.len() >= MAX_COMPONENT_DEPTH {
+            return Err(TemplateError::Render(format!(
+                "Components can be nested at most {MAX_COMPONENT_DEPTH} levels deep"
+            )));
+        }
+        Ok(())
+    };
+
+    // Partials render in the context they are included from, so they are simply inlined.
+    let partial_tag = Regex::new(r"\{\{>\s*(.*?)\s*\}\}").unwrap();
+    let mut error = None;
+    let contents = partial_tag.replace_all(contents, |captures: &Captures| {
+        let name = &captures[1];
+        let result = enter(name, stack).and_then(|_| {
+            let partial = read_component(directory, name, extension)?;
+            stack.push(name.to_string());
+            let expanded = expand(directory, &partial, extension, stack);
+            stack.pop();
+            expanded
+        });
+
+        result.unwrap_or_else(|err| {
+            error.get_or_insert(err);
+            String::new()
+        })
+    });
+    if let Some(err) = error {
+        return Err(err);
+    }
+
+    let prop_placeholder = Regex::new(r"\{\{\s*@([A-Za-z0-9_-]+)\s*\}\}").unwrap();
+    let slot_tag = Regex::new(r"<slot( ?)/>|<slot>(.*?)</slot>").unwrap();
+
+    let mut result = String::new();
+    let mut remaining = contents.to_string();
+
+    while let Some(tag) = find_component_tag(&remaining)? {
+        enter(&tag.name, stack)?;
+
+        // The slot belongs to the template using the component, expand it in that context.
+        let slot = expand(directory, &tag.slot, extension, stack)?;
+
+        let component = read_component(directory, &tag.name, extension)?;
+        stack.push(tag.name.clone());
+        let component = expand(directory, &component, extension, stack)?;
+        stack.pop();
+
+        let component = prop_placeholder.replace_all(&component, |captures: &Captures| {
+            tag.props.get(&captures[1]).cloned().unwrap_or_default()
+        });
+        // `<slot>Default</slot>` is used when the tag has no content.
+        let component = slot_tag.replace_all(&component, |captures: &Captures| {
+            if slot.trim().is_empty() {
+                captures.get(2).map_or("", |m| m.as_str()).to_string()
+            } else {
+                slot.clone()
+            }
+        });
+
+        result.push_str(&remaining[..tag.start]);
+        result.push_str(&component);
+        remaining = remaining[tag.end..].to_string();
+    }
+
+    result.push_str(&remaining);
+    Ok(result)
+}
+
+/// Inline the partials (`{{> footer}}`) and component tags (`<x-button href="...">Text</x-button>`)
+/// of a template, from the component directory. Components read their props as `{{@href}}`, and
+/// place the content between their tags at `<slot />`.
+pub fn expand_components(contents: &str, extension: &str) -> Result<String, TemplateError> {
+    expand(&get_component_directory(), contents, extension, &mut vec![])
+}
+
+#[test]
+fn test_expand_components() {
+    let directory = std::env::temp_dir().join(format!("meel-components-{}", std::process::id()));
+    fs::create_dir_all(directory.join("cards")).unwrap();
+    for (name, contents) in [
+        (
+            "button",
+            r#"<a href="{{@href}}" class="{{@class}}"><slot>Click here</slot></a>"#,
+        ),
+        ("cards/card", "<div>{{> title}}<slot /></div>"),
+        ("title", "<h1>{{ title }}</h1>"),
+        ("loop", "<x-loop />"),
+        ("ping", "{{> pong}}"),
+        ("pong", "{{> ping}}"),
+    ] {
+        fs::write(directory.join(format!("{name}.mustache")), contents).unwrap();
+    }
+    let directory_name = directory.display().to_string();
+    let expand_components =
+        |contents: &str, extension: &str| expand(&directory_name, contents, extension, &mut vec![]);
+
+    assert_eq!(
+        expand_components(
+            r#"<x-cards/card><x-button href="{{ url }}">Go</x-button></x-cards/card>"#,
+            "mustache"
+        )
+        .unwrap(),
+        r#"<div><h1>{{ title }}</h1><a href="{{ url }}" class="">Go</a></div>"#
+    );
+
+    // Components can be nested in their own slot.
+    assert_eq!(
+        expand_components(
+            "<x-button href='/a'><x-button href='/b'>B</x-button></x-button>",
+            "mustache"
+        )
+        .unwrap(),
+        r#"<a href="/a" class=""><a href="/b" class="">B</a></a>"#
+    );
+    assert_eq!(
+        expand_components(r#"<x-button href="/" class="big" />"#, "mustache").unwrap(),
+        r#"<a href="/" class="big">Click here</a>"#
+    );
+
+    assert!(matches!(
+        expand_components("<x-loop />", "mustache"),
+        Err(TemplateError::Render(_))
+    ));
+    assert!(matches!(
+        expand_components("{{> ping}}", "mustache"),
+        Err(TemplateError::Render(_))
+    ));
+    assert!(matches!(
+        expand_components("{{> ../templates/secret}}", "mustache"),
+        Err(TemplateError::InvalidName(_))
+    ));
+    assert!(matches!(
+        expand_components("<x-missing />", "mustache"),
+        Err(TemplateError::NotFound(_))
+    ));
+
+    fs::remove_dir_all(directory).unwrap();
+}
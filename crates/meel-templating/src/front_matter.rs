@@ -0,0 +1,171 @@
+use crate::templating::TemplateError;
+
+/// The settings a template declares in a front matter block at the top of its file:
+///
+/// ```text
+/// ---
+/// subject: Welcome, {{name}}!
+/// sender: Meel <noreply@example.com>
+/// required: [name, confirm_url]
+/// ---
+/// ```
+///
+/// Values are kept as declared, the subject and preheader may contain placeholders.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FrontMatter {
+    pub subject: Option<String>,
+    /// The preview text shown after the subject in most mail clients.
+    pub preheader: Option<String>,
+    pub sender: Option<String>,
+    pub reply_to: Option<String>,
+    /// Variables the template data must contain, nested ones written as `user.name`.
+    pub required: Vec<String>,
+    pub category: Option<String>,
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+
+    value
+}
+
+/// Parse `[a, b]` or `a, b` into a list.
+fn parse_list(value: &str) -> Vec<String> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+        .unwrap_or(value);
+
+    value
+        .split(',')
+        .map(unquote)
+        .filter(|item| !item.is_empty())
+        .map(|item| item.to_string())
+        .collect()
+}
+
+/// Split the front matter off the contents of a template, returning it with the rest of the
+/// template. Templates without front matter get the defaults.
+pub fn parse_front_matter(contents: &str) -> Result<(FrontMatter, &str), TemplateError> {
+    let contents_without_bom = contents.trim_start_matches('\u{feff}');
+
+    let Some(block) = contents_without_bom
+        .strip_prefix("---\n")
+        .or_else(|| contents_without_bom.strip_prefix("---\r\n"))
+    else {
+        return Ok((FrontMatter::default(), contents));
+    };
+
+    let mut front_matter = FrontMatter::default();
+    let mut list_key: Option<&str> = None;
+    let mut offset = 0;
+
+    for line in block.split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim_end();
+
+        if line == "---" {
+            return Ok((front_matter, &block[offset..]));
+        }
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        // A YAML style list item, following a key without a value.
+        if let Some(item) = trimmed.strip_prefix("- ") {
+            match list_key {
+                Some("required") => front_matter.required.push(unquote(item).to_string()),
+                _ => {
+                    return Err(TemplateError::Render(format!(
+                        "Unexpected list item `{trimmed}` in front matter"
+                    )));
+                }
+            }
+            continue;
+        }
+
+        let Some((key, value)) = trimmed.split_once(':') else {
+            return Err(TemplateError::Render(format!(
+                "Invalid front matter line `{trimmed}`, expected `key: value`"
+            )));
+        };
+        let key = key.trim();
+        let value = unquote(value);
+        list_key = Some(key);
+
+        let text = || (!value.is_empty()).then(|| value.to_string());
+        match key {
+            "subject" => front_matter.subject = text(),
+            "preheader" => front_matter.preheader = text(),
+            "sender" => front_matter.sender = text(),
+            "reply_to" => front_matter.reply_to = text(),
+            "category" => front_matter.category = text(),
+            "required" => front_matter.required = parse_list(value),
+            _ => {
+                return Err(TemplateError::Render(format!(
+                    "Unknown front matter key `{key}`"
+                )));
+            }
+        }
+    }
+
+    Err(TemplateError::Render(
+        "The front matter of the template is never closed with `---`".to_string(),
+    ))
+}
+
+#[test]
+fn test_parse_front_matter() {
+    let (front_matter, body) = parse_front_matter(
+        "---\n\
+        subject: Welcome, {{name}}!\n\
+        preheader: \"Confirm your email: it only takes a minute\"\n\
+        sender: Meel <noreply@example.com>\n\
+        # Used to filter mails by.\n\
+        category: onboarding\n\
+        required: [name, 'user.email']\n\
+        ---\n\
+        <h1>Hello</h1>\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        front_matter,
+        FrontMatter {
+            subject: Some("Welcome, {{name}}!".to_string()),
+            preheader: Some("Confirm your email: it only takes a minute".to_string()),
+            sender: Some("Meel <noreply@example.com>".to_string()),
+            reply_to: None,
+            required: vec!["name".to_string(), "user.email".to_string()],
+            category: Some("onboarding".to_string()),
+        }
+    );
+    assert_eq!(body, "<h1>Hello</h1>\n");
+
+    let (front_matter, body) =
+        parse_front_matter("---\nrequired:\n  - name\n  - url\n---\nHi").unwrap();
+    assert_eq!(front_matter.required, vec!["name", "url"]);
+    assert_eq!(body, "Hi");
+
+    let (front_matter, body) = parse_front_matter("<p>--- no front matter</p>").unwrap();
+    assert_eq!(front_matter, FrontMatter::default());
+    assert_eq!(body, "<p>--- no front matter</p>");
+
+    assert!(matches!(
+        parse_front_matter("---\nsubjcet: Hello\n---\n"),
+        Err(TemplateError::Render(_))
+    ));
+    assert!(matches!(
+        parse_front_matter("---\nsubject: Hello\n"),
+        Err(TemplateError::Render(_))
+    ));
+}
@@ -1 +1,5 @@
-pub mod templating;
\ No newline at end of file
+pub mod components;
+pub mod front_matter;
+pub mod locales;
+pub mod plain_text;
+pub mod templating;
@@ -0,0 +1,167 @@
+use regex::{Captures, Regex};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+
+use crate::templating::TemplateError;
+
+pub type Catalog = HashMap<String, String>;
+
+pub fn get_locale_directory() -> String {
+    format!(
+        "{}/locales",
+        meel_utils::env::get_var("MEEL_DATA_DIRECTORY", Some("./data")).unwrap()
+    )
+}
+
+/// The locale used when a mail doesn't have one, and the last fallback for those that do.
+fn get_default_locale() -> String {
+    meel_utils::env::get_var("MEEL_DEFAULT_LOCALE", Some("en"))
+        .filter(|locale| is_valid_locale(locale))
+        .unwrap_or_else(|| "en".to_string())
+}
+
+/// Whether the locale looks like a language tag, such as `nl` or `pt-BR`.
+pub fn is_valid_locale(locale: &str) -> bool {
+    let mut parts = locale.split(['-', '_']);
+
+    let language_valid = parts.next().is_some_and(|language| {
+        (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic())
+    });
+
+    language_valid
+        && parts.all(|part| {
+            (2..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+fn locale_chain(locale: Option<&str>, default_locale: &str) -> Vec<String> {
+    let mut chain: Vec<String> = vec![];
+
+    for locale in [locale, Some(default_locale)].into_iter().flatten() {
+        let locale = locale.trim().replace('_', "-");
+        if !is_valid_locale(&locale) {
+            continue;
+        }
+
+        // `pt-BR` falls back to `pt`.
+        let mut parts: Vec<&str> = locale.split('-').collect();
+        while !parts.is_empty() {
+            let candidate = parts.join("-");
+            if !chain.contains(&candidate) {
+                chain.push(candidate);
+            }
+            parts.pop();
+        }
+    }
+
+    chain
+}
+
+/// The locales to try for a mail, from most to least specific, ending with the default locale.
+pub fn get_locale_chain(locale: Option<&str>) -> Vec<String> {
+    locale_chain(locale, &get_default_locale())
+}
+
+#[test]
+fn test_locale_chain() {
+    assert_eq!(locale_chain(Some("nl"), "en"), vec!["nl", "en"]);
+    assert_eq!(locale_chain(Some("pt_BR"), "en"), vec!["pt-BR", "pt", "en"]);
+    assert_eq!(locale_chain(Some("en-GB"), "en"), vec!["en-GB", "en"]);
+    assert_eq!(locale_chain(None, "en"), vec!["en"]);
+    assert_eq!(locale_chain(Some("../secrets"), "en"), vec!["en"]);
+}
+
+/// Flatten nested catalog objects into dotted keys, `{"welcome": {"title": ".."}}` becomes
+/// `welcome.title`.
+fn flatten(prefix: &str, value: &Value, catalog: &mut Catalog) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten(&key, value, catalog);
+            }
+        }
+        Value::String(string) => {
+            catalog.insert(prefix.to_string(), string.clone());
+        }
+        Value::Null => {}
+        value => {
+            catalog.insert(prefix.to_string(), value.to_string());
+        }
+    }
+}
+
+/// Load the catalogs of the locale chain, translations of more specific locales take precedence.
+/// Locales without a catalog are skipped.
+fn load_catalog(directory: &str, chain: &[String]) -> Result<Catalog, TemplateError> {
+    let mut catalog = Catalog::new();
+
+    for locale in chain.iter().rev() {
+        let Ok(contents) = fs::read_to_string(format!("{directory}/{locale}.json")) else {
+            continue;
+        };
+
+        let value: Value = serde_json::from_str(&contents).map_err(|err| {
+            TemplateError::Read(format!(
+                "Failed to parse locale catalog {locale}.json: {err}"
+            ))
+        })?;
+        flatten("", &value, &mut catalog);
+    }
+
+    Ok(catalog)
+}
+
+/// Replace the `{{#t}}key{{/t}}` tags in the contents. Keys without a translation are left as is,
+/// so a missing translation shows up in the mail instead of an empty string.
+fn apply_translations(contents: &str, catalog: &Catalog) -> String {
+    let translation_tag = Regex::new(r"(?s)\{\{#\s*t\s*\}\}(.*?)\{\{/\s*t\s*\}\}").unwrap();
+
+    translation_tag
+        .replace_all(contents, |captures: &Captures| {
+            let key = captures[1].trim();
+            catalog.get(key).cloned().unwrap_or_else(|| key.to_string())
+        })
+        .to_string()
+}
+
+#[test]
+fn test_apply_translations() {
+    let mut catalog = Catalog::new();
+    flatten(
+        "",
+        &serde_json::json!({
+            "greeting": "Hallo {{ name }}",
+            "welcome": { "title": "Welkom", "count": 3 }
+        }),
+        &mut catalog,
+    );
+
+    assert_eq!(
+        apply_translations(
+            "<h1>{{#t}}welcome.title{{/t}}</h1><p>{{#t}} greeting {{/t}}, {{#t}}missing{{/t}}</p>",
+            &catalog
+        ),
+        "<h1>Welkom</h1><p>Hallo {{ name }}, missing</p>"
+    );
+    assert_eq!(
+        apply_translations("{{#t}}welcome.count{{/t}}", &catalog),
+        "3"
+    );
+}
+
+/// Translate the `{{#t}}key{{/t}}` tags in a template or subject, using the catalogs in the locale
+/// directory. Translations may contain placeholders, which are rendered with the rest of the template.
+pub fn translate(contents: &str, locale: Option<&str>) -> Result<String, TemplateError> {
+    if !contents.contains("{{#") {
+        return Ok(contents.to_string());
+    }
+
+    let catalog = load_catalog(&get_locale_directory(), &get_locale_chain(locale))?;
+    Ok(apply_translations(contents, &catalog))
+}
@@ -0,0 +1,193 @@
+use std::sync::LazyLock;
+
+use html2text::render::{TaggedLine, TextDecorator};
+use regex::Regex;
+
+/// Writes links as `text (url)`, lists with `-` and numbers, and leaves out any other markup.
+#[derive(Clone, Default)]
+struct PlainTextDecorator {
+    /// The urls of the open links, `None` for links that don't need their url written out.
+    links: Vec<Option<String>>,
+}
+
+impl TextDecorator for PlainTextDecorator {
+    type Annotation = ();
+
+    fn decorate_link_start(&mut self, url: &str) -> (String, Self::Annotation) {
+        // Links within the mail itself mean nothing outside of the HTML part.
+        let url = (!url.is_empty() && !url.starts_with('#')).then(|| url.to_string());
+        self.links.push(url);
+        (String::new(), ())
+    }
+
+    fn decorate_link_end(&mut self) -> String {
+        match self.links.pop().flatten() {
+            Some(url) => format!(" ({url})"),
+            None => String::new(),
+        }
+    }
+
+    fn decorate_em_start(&self) -> (String, Self::Annotation) {
+        (String::new(), ())
+    }
+
+    fn decorate_em_end(&self) -> String {
+        String::new()
+    }
+
+    fn decorate_strong_start(&self) -> (String, Self::Annotation) {
+        (String::new(), ())
+    }
+
+    fn decorate_strong_end(&self) -> String {
+        String::new()
+    }
+
+    fn decorate_strikeout_start(&self) -> (String, Self::Annotation) {
+        (String::new(), ())
+    }
+
+    fn decorate_strikeout_end(&self) -> String {
+        String::new()
+    }
+
+    fn decorate_code_start(&self) -> (String, Self::Annotation) {
+        (String::new(), ())
+    }
+
+    fn decorate_code_end(&self) -> String {
+        String::new()
+    }
+
+    fn decorate_preformat_first(&self) -> Self::Annotation {}
+
+    fn decorate_preformat_cont(&self) -> Self::Annotation {}
+
+    fn decorate_image(&mut self, _src: &str, title: &str) -> (String, Self::Annotation) {
+        (title.to_string(), ())
+    }
+
+    fn header_prefix(&self, _level: usize) -> String {
+        String::new()
+    }
+
+    fn quote_prefix(&self) -> String {
+        "> ".to_string()
+    }
+
+    fn unordered_item_prefix(&self) -> String {
+        "- ".to_string()
+    }
+
+    fn ordered_item_prefix(&self, i: i64) -> String {
+        format!("{i}. ")
+    }
+
+    fn make_subblock_decorator(&self) -> Self {
+        Self::default()
+    }
+
+    fn finalise(&mut self, _urls: Vec<String>) -> Vec<TaggedLine<Self::Annotation>> {
+        Vec::new()
+    }
+}
+
+/// A link followed by its own url, as in `help@example.com (mailto:help@example.com)`.
+static REPEATED_URL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(\S+) \((?:mailto:)?([^\s()]+)\)").expect("the repeated url regex is valid")
+});
+
+/// Convert a rendered HTML mail to plain text, for mails without a plain text template. Links are
+/// written as `text (url)`, lists get bullets or numbers, and content hidden with inline CSS, like
+/// the preheader, is left out.
+pub fn html_to_text(html: &str) -> String {
+    let text = match html2text::config::with_decorator(PlainTextDecorator::default())
+        .use_doc_css()
+        .no_table_borders()
+        .link_footnotes(false)
+        .string_from_read(html.as_bytes(), usize::MAX)
+    {
+        Ok(text) => text,
+        // Only happens for documents nested too deeply to render, fall back to no text at all.
+        Err(_) => return String::new(),
+    };
+
+    let text = REPEATED_URL.replace_all(&text, |captures: &regex::Captures| {
+        if captures[1].ends_with(&captures[2]) {
+            captures[1].to_string()
+        } else {
+            captures[0].to_string()
+        }
+    });
+
+    text.lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+#[test]
+fn test_html_to_text() {
+    let html = r#"<!DOCTYPE html>
+<html>
+<head><title>Welcome</title><style>p { color: red; }</style></head>
+<body>
+    <div style="display: none; max-height: 0">Preview text&zwnj;&nbsp;&zwnj;</div>
+    <!--[if mso]><table><tr><td><![endif]-->
+    <h1>Hello   Boris &amp; friends</h1>
+    <p>Please <a href="https://example.com/confirm?a=1&amp;b=2">confirm your email</a>,
+    or mail <a href="mailto:help@example.com">help@example.com</a>.<br>Thanks!</p>
+    <ul>
+        <li>One</li>
+        <li>Two
+            <ol start="3"><li>Three</li><li>Four</ol>
+        </li>
+    </ul>
+    <table>
+        <thead><tr><th>Item</th><th>Price</th></tr></thead>
+        <tbody>
+            <tr><td>Coffee</td><td>&euro;2.50</td></tr>
+            <tr><td>Apple pie</td><td>&euro;4</td></tr>
+        </tbody>
+    </table>
+    <table><tr><td><p>Layout</p></td><td><img src="logo.png" alt="Meel"></td></tr></table>
+</body>
+</html>"#;
+
+    assert_eq!(
+        html_to_text(html),
+        "Hello Boris & friends\n\
+        \n\
+        Please confirm your email (https://example.com/confirm?a=1&b=2), or mail help@example.com.\n\
+        Thanks!\n\
+        - One\n\
+        - Two\n  \
+          3. Three\n  \
+          4. Four\n\
+        \n\
+        Item      Price\n\
+        Coffee    €2.50\n\
+        Apple pie €4\n\
+        \n\
+        Layout Meel"
+    );
+}
+
+#[test]
+fn test_html_to_text_invalid_markup() {
+    assert_eq!(
+        html_to_text("a < b and <b>bold</i> text"),
+        "a < b and bold text"
+    );
+    assert_eq!(
+        html_to_text("<p>Unclosed <a href='#top'>top"),
+        "Unclosed top"
+    );
+    assert_eq!(
+        html_to_text("Tom &amp Jerry &#8211; &#x263A; <!-- hidden --><script>alert(1)</script>"),
+        "Tom & Jerry – ☺"
+    );
+    assert_eq!(html_to_text(""), "");
+}
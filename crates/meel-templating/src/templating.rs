@@ -1,14 +1,46 @@
+use crate::components::expand_components;
+use crate::front_matter::{FrontMatter, parse_front_matter};
+use crate::locales;
 use ammonia::clean_text;
-use minify_html::{minify, Cfg};
+use minify_html::{Cfg, minify};
 use regex::Regex;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::sync::LazyLock;
 
 pub type TemplateDataMap = HashMap<String, Value>;
 
+#[derive(Debug, PartialEq)]
+pub enum TemplateError {
+    /// The template name is empty, or tries to escape the template directory.
+    InvalidName(String),
+    NotFound(String),
+    /// The template or its layouts could not be read.
+    Read(String),
+    /// The template could not be compiled or rendered with the given data.
+    Render(String),
+    /// The data lacks variables the template requires in its front matter.
+    MissingVariables(Vec<String>),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::InvalidName(message)
+            | TemplateError::NotFound(message)
+            | TemplateError::Read(message)
+            | TemplateError::Render(message) => f.write_str(message),
+            TemplateError::MissingVariables(variables) => {
+                write!(f, "Missing template variables: {}", variables.join(", "))
+            }
+        }
+    }
+}
+
 pub fn get_template_directory() -> String {
     format!(
         "{}/templates",
@@ -41,42 +73,72 @@ fn get_globals() -> Result<TemplateDataMap, String> {
     Ok(globals)
 }
 
+fn validate_locale(locale: Option<&str>) -> Result<(), TemplateError> {
+    match locale {
+        Some(locale) if !locales::is_valid_locale(locale) => Err(TemplateError::InvalidName(
+            format!("Invalid locale `{locale}`"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Open the file of a template, preferring the variants for the locale chain (`welcome.nl.mustache`)
+/// over the template without a locale (`welcome.mustache`).
+fn open_localized_file(
+    template_name: &str,
+    extension: &str,
+    locale: Option<&str>,
+) -> Result<File, TemplateError> {
+    let template_directory = get_template_directory();
+
+    locales::get_locale_chain(locale)
+        .iter()
+        .map(|locale| format!("{template_directory}/{template_name}.{locale}.{extension}"))
+        .chain([format!("{template_directory}/{template_name}.{extension}")])
+        .find_map(|template_path| File::open(template_path).ok())
+        .ok_or_else(|| TemplateError::NotFound(format!("Template {template_name} not found")))
+}
+
 /// Get a template file based on the name. The name may contain a directory path.
-fn get_template_file(template_name: String) -> Result<File, String> {
+fn get_template_file(template_name: String, locale: Option<&str>) -> Result<File, TemplateError> {
     if template_name.is_empty() {
-        return Err("Template name cannot be empty".to_string());
+        return Err(TemplateError::InvalidName(
+            "Template name cannot be empty".to_string(),
+        ));
     }
 
     if template_name.contains("..") {
-        return Err("Template name cannot contain '..'".to_string());
+        return Err(TemplateError::InvalidName(
+            "Template name cannot contain '..'".to_string(),
+        ));
     }
 
-    let template_path = format!("{}/{}.mustache", get_template_directory(), template_name);
-
-    match File::open(template_path) {
-        Ok(file) => Ok(file),
-        Err(_) => Err(format!("Template {template_name} not found")),
-    }
+    open_localized_file(&template_name, "mustache", locale)
 }
 
 /// Get a plain text template file based on the name. The name may contain a directory path.
-fn get_plain_text_file(template_name: String) -> Result<File, String> {
-    let template_path = format!("{}/{}.txt", get_template_directory(), template_name);
-
-    match File::open(template_path) {
-        Ok(file) => Ok(file),
-        Err(_) => Err(format!("Template {template_name} not found")),
+fn get_plain_text_file(template_name: String, locale: Option<&str>) -> Result<File, TemplateError> {
+    if template_name.contains("..") {
+        return Err(TemplateError::InvalidName(
+            "Template name cannot contain '..'".to_string(),
+        ));
     }
+
+    open_localized_file(&template_name, "txt", locale)
 }
 
 /// Recursively apply the layout to the template until the root layout is reached.
-fn apply_layout(path: String, contents: String) -> Result<String, String> {
+fn apply_layout(path: String, contents: String) -> Result<String, TemplateError> {
     let template_directory = get_template_directory();
     let root_template_path = Path::new(&template_directory);
 
     let template_parent_path = match Path::new(&path).parent() {
         Some(parent) => parent,
-        None => return Err("Failed to get parent directory".to_string()),
+        None => {
+            return Err(TemplateError::Read(
+                "Failed to get parent directory".to_string(),
+            ));
+        }
     };
 
     let layout_path = format!("{}/layout.mustache", template_parent_path.display());
@@ -84,13 +146,21 @@ fn apply_layout(path: String, contents: String) -> Result<String, String> {
     let layout_contents = if Path::new(&layout_path).exists() {
         let mut layout_file = match File::open(&layout_path) {
             Ok(file) => file,
-            Err(_) => return Err("Failed to open layout file".to_string()),
+            Err(_) => {
+                return Err(TemplateError::Read(
+                    "Failed to open layout file".to_string(),
+                ));
+            }
         };
 
         let mut layout_contents = String::new();
         match layout_file.read_to_string(&mut layout_contents) {
             Ok(_) => layout_contents,
-            Err(_) => return Err("Failed to read layout file".to_string()),
+            Err(_) => {
+                return Err(TemplateError::Read(
+                    "Failed to read layout file".to_string(),
+                ));
+            }
         }
     } else {
         "<slot />".to_string()
@@ -98,7 +168,7 @@ fn apply_layout(path: String, contents: String) -> Result<String, String> {
 
     let re = match Regex::new(r"<slot( ?)/>|<slot>(.*?)</slot>") {
         Ok(re) => re,
-        Err(_) => return Err("Failed to compile regex".to_string()),
+        Err(_) => return Err(TemplateError::Render("Failed to compile regex".to_string())),
     };
 
     // TODO: The indenting isn't correct for nested slots. We might actually want to compress the content though.
@@ -116,10 +186,9 @@ pub fn apply_placeholders(
     contents: String,
     data: TemplateDataMap,
     allow_html: bool,
-) -> Result<String, String> {
-    // TODO: would be cool if we could reference (i.e. import) other templates which I believe is supported by mustache
-    let template =
-        mustache::compile_str(&contents).map_err(|_| "Failed to compile template".to_string())?;
+) -> Result<String, TemplateError> {
+    let template = mustache::compile_str(&contents)
+        .map_err(|err| TemplateError::Render(format!("Failed to compile template: {err}")))?;
 
     let cleaned_data = if allow_html {
         data
@@ -152,7 +221,7 @@ pub fn apply_placeholders(
 
     template
         .render_to_string(&cleaned_data)
-        .map_err(|_| "Failed to render template".to_string())
+        .map_err(|err| TemplateError::Render(format!("Failed to render template: {err}")))
 }
 
 #[test]
@@ -197,61 +266,270 @@ fn test_apply_placeholders() {
     );
 }
 
-/// Render a template with the given data.
+/// A placeholder that mustache would HTML escape, or one that is already unescaped.
+static PLACEHOLDER_TAG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\{\{\{[^{}]*\}\}\}|\{\{(\s*[^{}#/^>!&=\s][^{}]*)\}\}")
+        .expect("the placeholder tag regex is valid")
+});
+
+/// Apply placeholders to plain text. Nothing is escaped, as entities like `&amp;` would show up
+/// as they are in the text part of a mail, breaking links such as the `unsubscribe_url`.
+fn apply_text_placeholders(contents: &str, data: TemplateDataMap) -> Result<String, TemplateError> {
+    let contents =
+        PLACEHOLDER_TAG.replace_all(contents, |captures: &regex::Captures| {
+            match captures.get(1) {
+                Some(name) => format!("{{{{&{}}}}}", name.as_str()),
+                None => captures[0].to_string(),
+            }
+        });
+
+    apply_placeholders(contents.into_owned(), data, true)
+}
+
+/// Find the variables of `required` that are missing or null in the data.
+fn find_missing_variables(data: &TemplateDataMap, required: &[String]) -> Vec<String> {
+    required
+        .iter()
+        .filter(|variable| {
+            let mut path = variable.split('.');
+            let value = path
+                .next()
+                .and_then(|key| data.get(key))
+                .and_then(|value| path.try_fold(value, |value, key| value.get(key)));
+
+            matches!(value, None | Some(Value::Null))
+        })
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn test_find_missing_variables() {
+    let data = TemplateDataMap::from([
+        ("name".to_string(), Value::String("".to_string())),
+        (
+            "user".to_string(),
+            serde_json::json!({ "email": "a@b.c", "phone": null }),
+        ),
+    ]);
+    let required: Vec<String> = ["name", "url", "user.email", "user.phone", "user.id"]
+        .iter()
+        .map(|variable| variable.to_string())
+        .collect();
+
+    assert_eq!(
+        find_missing_variables(&data, &required),
+        vec!["url", "user.phone", "user.id"]
+    );
+}
+
+static BODY_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)<body[^>]*>").expect("the body tag regex is valid"));
+
+/// Render the preheader like the rest of the template, and escape it, as it ends up in the HTML.
+fn render_preheader(
+    preheader: &str,
+    data: TemplateDataMap,
+    locale: Option<&str>,
+) -> Result<String, TemplateError> {
+    let preheader = apply_text_placeholders(&locales::translate(preheader, locale)?, data)?;
+    Ok(clean_text(&preheader))
+}
+
+/// Hide the rendered preheader at the start of the body, so mail clients show it as preview text.
+fn insert_preheader(contents: String, preheader: &str) -> String {
+    let preheader = format!(
+        "<div style=\"display:none;max-height:0;max-width:0;overflow:hidden;opacity:0;mso-hide:all\">{preheader}</div>"
+    );
+
+    match BODY_TAG.find(&contents) {
+        Some(body) => format!(
+            "{}{}{}",
+            &contents[..body.end()],
+            preheader,
+            &contents[body.end()..]
+        ),
+        None => preheader + &contents,
+    }
+}
+
+#[test]
+fn test_render_preheader() {
+    let data =
+        TemplateDataMap::from([("name".to_string(), Value::String("<b>Tom</b>".to_string()))]);
+    let preheader = render_preheader("Hi {{name}} <3", data, None).unwrap();
+
+    assert!(preheader.contains("Tom") && !preheader.contains('<') && !preheader.contains("{{"));
+    assert_eq!(
+        insert_preheader(
+            "<html><BODY class=\"a\"><p>Hi</p></body></html>".to_string(),
+            "Hi"
+        ),
+        "<html><BODY class=\"a\"><div style=\"display:none;max-height:0;max-width:0;overflow:hidden;opacity:0;mso-hide:all\">Hi</div><p>Hi</p></body></html>"
+    );
+}
+
+/// A rendered template, with the front matter it declared.
+pub struct RenderedTemplate {
+    pub html: String,
+    pub front_matter: FrontMatter,
+}
+
+/// Render a template with the given data, in the given locale or the default one.
 pub fn render(
     template_name: String,
     mut data: TemplateDataMap,
     allow_html: bool,
     minify_html: bool,
-) -> Result<String, String> {
-    let mut file = get_template_file(template_name.clone())?;
+    locale: Option<&str>,
+) -> Result<RenderedTemplate, TemplateError> {
+    validate_locale(locale)?;
+    let mut file = get_template_file(template_name.clone(), locale)?;
 
     let mut contents = String::new();
     match file.read_to_string(&mut contents) {
         Ok(_) => (),
-        Err(_) => return Err("Failed to read template file".to_string()),
+        Err(_) => {
+            return Err(TemplateError::Read(
+                "Failed to read template file".to_string(),
+            ));
+        }
     };
 
+    let (front_matter, contents) = parse_front_matter(&contents)?;
+
     let globals = get_globals().unwrap_or_default();
     data.extend(globals);
 
-    let content = apply_placeholders(
-        apply_layout(
-            format!("{}/{}", get_template_directory(), &template_name),
-            contents,
-        )?,
-        data,
-        allow_html,
+    let missing_variables = find_missing_variables(&data, &front_matter.required);
+    if !missing_variables.is_empty() {
+        return Err(TemplateError::MissingVariables(missing_variables));
+    }
+
+    let contents = apply_layout(
+        format!("{}/{}", get_template_directory(), &template_name),
+        contents.to_string(),
     )?;
+    let contents = locales::translate(&expand_components(&contents, "mustache")?, locale)?;
+    let mut content = apply_placeholders(contents, data.clone(), allow_html)?;
+    if let Some(preheader) = &front_matter.preheader {
+        content = insert_preheader(content, &render_preheader(preheader, data, locale)?);
+    }
 
     if !minify_html {
-        return Ok(content);
+        return Ok(RenderedTemplate {
+            html: content,
+            front_matter,
+        });
     }
 
     let mut cfg = Cfg::new();
     cfg.keep_closing_tags = true;
 
-    match String::from_utf8(minify(content.as_ref(), &cfg)) {
-        Ok(content) => Ok(content),
+    let html = match String::from_utf8(minify(content.as_ref(), &cfg)) {
+        Ok(content) => content,
         // We failed to minify here so return the original content
-        Err(_) => Ok(content),
-    }
+        Err(_) => content,
+    };
+
+    Ok(RenderedTemplate { html, front_matter })
 }
 
 pub fn render_plain_text(
     template_name: String,
     mut data: TemplateDataMap,
-) -> Result<String, String> {
-    let mut file = get_plain_text_file(template_name.clone())?;
+    locale: Option<&str>,
+) -> Result<String, TemplateError> {
+    validate_locale(locale)?;
+    let mut file = get_plain_text_file(template_name, locale)?;
 
     let mut contents = String::new();
     match file.read_to_string(&mut contents) {
         Ok(_) => (),
-        Err(_) => return Err("Failed to read template file".to_string()),
+        Err(_) => {
+            return Err(TemplateError::Read(
+                "Failed to read template file".to_string(),
+            ));
+        }
     };
 
     let globals = get_globals().unwrap_or_default();
     data.extend(globals);
 
-    apply_placeholders(contents, data, false)
+    render_plain_text_contents(&contents, data, locale)
+}
+
+fn render_plain_text_contents(
+    contents: &str,
+    data: TemplateDataMap,
+    locale: Option<&str>,
+) -> Result<String, TemplateError> {
+    let contents = locales::translate(&expand_components(contents, "txt")?, locale)?;
+    apply_text_placeholders(&contents, data)
+}
+
+#[test]
+fn test_render_plain_text_contents() {
+    let data = TemplateDataMap::from([
+        (
+            "name".to_string(),
+            Value::String("Tom & <Jerry>".to_string()),
+        ),
+        (
+            "unsubscribe_url".to_string(),
+            Value::String("https://meel.example/unsubscribe?token=a/b=&list=1".to_string()),
+        ),
+    ]);
+
+    assert_eq!(
+        render_plain_text_contents(
+            "Hi {{ name }}, {{{name}}}.\nUnsubscribe: {{unsubscribe_url}}",
+            data,
+            None
+        )
+        .unwrap(),
+        "Hi Tom & <Jerry>, Tom & <Jerry>.\nUnsubscribe: https://meel.example/unsubscribe?token=a/b=&list=1"
+    );
+}
+
+#[test]
+fn test_render_errors() {
+    assert!(matches!(
+        render("".to_string(), TemplateDataMap::new(), false, true, None),
+        Err(TemplateError::InvalidName(_))
+    ));
+    assert!(matches!(
+        render(
+            "../secrets".to_string(),
+            TemplateDataMap::new(),
+            false,
+            true,
+            None
+        ),
+        Err(TemplateError::InvalidName(_))
+    ));
+    assert!(matches!(
+        render(
+            "does-not-exist".to_string(),
+            TemplateDataMap::new(),
+            false,
+            true,
+            None
+        ),
+        Err(TemplateError::NotFound(_))
+    ));
+    assert!(matches!(
+        render(
+            "welcome".to_string(),
+            TemplateDataMap::new(),
+            false,
+            true,
+            Some("../nl")
+        ),
+        Err(TemplateError::InvalidName(_))
+    ));
+    assert!(matches!(
+        apply_placeholders("{{#unclosed}}".to_string(), TemplateDataMap::new(), false),
+        Err(TemplateError::Render(_))
+    ));
 }
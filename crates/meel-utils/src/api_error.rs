@@ -1,14 +1,26 @@
 use std::collections::HashMap;
 
+use axum::Json;
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum ApiErrorCode {
     Unknown,
     NotFound,
+    Conflict,
+    Unauthorized,
+    Forbidden,
+    SenderNotAllowed,
+    RecipientSuppressed,
+    RateLimited,
+    ValidationError,
+    TemplateNotFound,
+    TemplateRenderError,
+    DatabaseError,
 }
 
 #[derive(Debug, Serialize)]
@@ -20,7 +32,12 @@ pub struct ApiError {
 }
 
 impl ApiError {
-    pub fn new(status_code: StatusCode, error_code: ApiErrorCode, message: String, details: HashMap<String, String>) -> Self {
+    pub fn new(
+        status_code: StatusCode,
+        error_code: ApiErrorCode,
+        message: String,
+        details: HashMap<String, String>,
+    ) -> Self {
         Self {
             status_code: status_code.as_u16(),
             error_code,
@@ -40,6 +57,35 @@ impl IntoResponse for ApiError {
         });
 
         // this unwrap should be safe, as we have a valid status code
-        (StatusCode::from_u16(self.status_code).unwrap(), body.to_string()).into_response()
+        (StatusCode::from_u16(self.status_code).unwrap(), Json(body)).into_response()
     }
-}
\ No newline at end of file
+}
+
+/// The error for a request whose JSON body, path or query string can't be parsed, with the status
+/// axum picked for it.
+fn rejection_error(status_code: StatusCode, message: String) -> ApiError {
+    ApiError::new(
+        status_code,
+        ApiErrorCode::ValidationError,
+        message,
+        HashMap::new(),
+    )
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        rejection_error(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        rejection_error(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        rejection_error(rejection.status(), rejection.body_text())
+    }
+}
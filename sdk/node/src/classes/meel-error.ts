@@ -1,15 +1,17 @@
+import { MeelErrorCode } from '../types';
+
 export interface MeelErrorConstructor {
 	status_code: number;
-	error_code: number;
+	error_code: MeelErrorCode | string;
 	message: string;
-	details: object;
+	details: Record<string, string>;
 }
 
 export class MeelError extends Error {
 	public readonly message: string;
 	public readonly code: number;
-	public readonly details: object;
-	public readonly error_code: number;
+	public readonly details: Record<string, string>;
+	public readonly error_code: MeelErrorCode | string;
 
 	public constructor(data: MeelErrorConstructor) {
 		super(data.message);
@@ -19,6 +21,18 @@ export class MeelError extends Error {
 		this.error_code = data.error_code;
 	}
 
+	/**
+	 * Check whether the error has the given error code.
+	 *
+	 * @example
+	 * if (error.is(MeelErrorCode.RATE_LIMITED)) {
+	 * 	await sleep(Number(error.details.retry_after) * 1000);
+	 * }
+	 */
+	public is(errorCode: MeelErrorCode): boolean {
+		return this.error_code === errorCode;
+	}
+
 	public toString(): string {
 		return `${this.code}: ${this.message}`;
 	}
@@ -7,6 +7,7 @@ import {
 	SentMeel,
 	SentMeelConstructor,
 } from '.';
+import { MeelErrorCode } from '../types';
 
 /**
  * MeelSender is a class that sends Meel instances to a specified base url.
@@ -14,7 +15,10 @@ import {
  * @example
  * import { Meel, MeelSender } from "meel";
  *
- * const sender = new MeelSender({ baseUrl: "http://localhost:8080" });
+ * const sender = new MeelSender({
+ * 	baseUrl: "http://localhost:8080",
+ * 	apiKey: process.env.MEEL_API_KEY,
+ * });
  *
  * const mail = new Meel({
  * 	recipient: "Boris <boris@example.com>",
@@ -30,9 +34,17 @@ import {
  */
 export class MeelSender {
 	private readonly baseUrl: string;
+	private readonly apiKey?: string;
 
-	public constructor({ baseUrl }: { baseUrl: string }) {
+	public constructor({
+		baseUrl,
+		apiKey,
+	}: {
+		baseUrl: string;
+		apiKey?: string;
+	}) {
 		this.baseUrl = baseUrl.endsWith('/') ? baseUrl.slice(0, -1) : baseUrl;
+		this.apiKey = apiKey;
 	}
 
 	/**
@@ -55,38 +67,45 @@ export class MeelSender {
 	 */
 	public async batchSend(mails: Meel[]): Promise<(SentMeel | MeelError)[]> {
 		const response = await Try(() =>
-			ky
-				.post<
-					(
-						| {
-								Ok: SentMeelConstructor;
-						  }
-						| {
-								Err: MeelErrorConstructor;
-						  }
-					)[]
-				>(`${this.baseUrl}/mails/send`, {
-					body: JSON.stringify(
-						mails.map(mail => mail.toPlainObject()),
-					),
-					headers: {
-						'Content-Type': 'application/json',
-					},
-				})
-				.json(),
+			ky.post(`${this.baseUrl}/mails/send`, {
+				body: JSON.stringify(mails.map(mail => mail.toPlainObject())),
+				headers: {
+					'Content-Type': 'application/json',
+					...(this.apiKey
+						? { Authorization: `Bearer ${this.apiKey}` }
+						: {}),
+				},
+				// Error responses are turned into a MeelError below.
+				throwHttpErrors: false,
+			}),
 		);
 
 		if (!response) {
 			throw new MeelError({
 				status_code: 500,
-				error_code: 0,
+				error_code: MeelErrorCode.UNKNOWN,
 				message: 'Failed to send mail',
 				details: {},
 			});
 		}
 
-		return response.map(data =>
-			'Ok' in data ? new SentMeel(data.Ok) : new MeelError(data.Err),
+		if (!response.ok) {
+			throw new MeelError(await response.json<MeelErrorConstructor>());
+		}
+
+		const results = await response.json<
+			(
+				| {
+						Ok: SentMeelConstructor;
+				  }
+				| {
+						Err: MeelErrorConstructor;
+				  }
+			)[]
+		>();
+
+		return results.map(result =>
+			'Ok' in result ? new SentMeel(result.Ok) : new MeelError(result.Err),
 		);
 	}
 }
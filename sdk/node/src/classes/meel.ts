@@ -2,35 +2,56 @@ import { MeelPriority } from '../types';
 import { removeUndefinedValues } from '../utility';
 
 export interface MeelConstructor {
-	recipient: string;
-	sender: string;
+	recipient?: string;
+	to?: string[];
+	cc?: string[];
+	bcc?: string[];
+	sender?: string;
 	template: string;
 	data: object;
-	subject: string;
+	subject?: string;
 	priority?: MeelPriority | number;
 	allow_html?: boolean;
 	minify_html?: boolean;
 	schedule_at?: string | Date;
 	reply_to?: string;
+	headers?: Record<string, string>;
+	list_unsubscribe?: string[];
+	list_unsubscribe_one_click?: boolean;
+	tags?: string[];
+	metadata?: Record<string, string>;
+	locale?: string;
 }
 
 /**
  * Meel is a class that represents a mail to be sent to a recipient.
  */
 export class Meel {
-	public recipient: string;
-	public sender: string;
+	public recipient?: string;
+	public to?: string[];
+	public cc?: string[];
+	public bcc?: string[];
+	public sender?: string;
 	public template: string;
 	public priority: MeelPriority | number;
 	public data: object;
-	public subject: string;
+	public subject?: string;
 	public allow_html?: boolean;
 	public minify_html?: boolean;
 	public schedule_at?: Date;
 	public reply_to?: string;
+	public headers?: Record<string, string>;
+	public list_unsubscribe?: string[];
+	public list_unsubscribe_one_click?: boolean;
+	public tags?: string[];
+	public metadata?: Record<string, string>;
+	public locale?: string;
 
 	public constructor(data: MeelConstructor) {
 		this.recipient = data.recipient;
+		this.to = data.to;
+		this.cc = data.cc;
+		this.bcc = data.bcc;
 		this.sender = data.sender;
 		this.subject = data.subject;
 		this.template = data.template;
@@ -39,6 +60,12 @@ export class Meel {
 		this.allow_html = data.allow_html;
 		this.minify_html = data.minify_html;
 		this.reply_to = data.reply_to;
+		this.headers = data.headers;
+		this.list_unsubscribe = data.list_unsubscribe;
+		this.list_unsubscribe_one_click = data.list_unsubscribe_one_click;
+		this.tags = data.tags;
+		this.metadata = data.metadata;
+		this.locale = data.locale;
 		this.schedule_at = data.schedule_at
 			? data.schedule_at instanceof Date
 				? data.schedule_at
@@ -52,6 +79,9 @@ export class Meel {
 	public toPlainObject(): MeelConstructor {
 		return removeUndefinedValues<MeelConstructor>({
 			recipient: this.recipient,
+			to: this.to,
+			cc: this.cc,
+			bcc: this.bcc,
 			sender: this.sender,
 			template: this.template,
 			priority: this.priority,
@@ -60,6 +90,12 @@ export class Meel {
 			minify_html: this.minify_html,
 			schedule_at: this.schedule_at?.toISOString(),
 			reply_to: this.reply_to,
+			headers: this.headers,
+			list_unsubscribe: this.list_unsubscribe,
+			list_unsubscribe_one_click: this.list_unsubscribe_one_click,
+			tags: this.tags,
+			metadata: this.metadata,
+			locale: this.locale,
 			subject: this.subject,
 		});
 	}
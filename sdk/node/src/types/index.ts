@@ -1 +1,2 @@
+export * from './meel-error-code';
 export * from './meel-priority';
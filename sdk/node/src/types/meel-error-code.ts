@@ -0,0 +1,17 @@
+/**
+ * The `error_code` values the Meel API returns, so errors can be handled by kind.
+ */
+export enum MeelErrorCode {
+	UNKNOWN = 'Unknown',
+	NOT_FOUND = 'NotFound',
+	CONFLICT = 'Conflict',
+	UNAUTHORIZED = 'Unauthorized',
+	FORBIDDEN = 'Forbidden',
+	SENDER_NOT_ALLOWED = 'SenderNotAllowed',
+	RECIPIENT_SUPPRESSED = 'RecipientSuppressed',
+	RATE_LIMITED = 'RateLimited',
+	VALIDATION_ERROR = 'ValidationError',
+	TEMPLATE_NOT_FOUND = 'TemplateNotFound',
+	TEMPLATE_RENDER_ERROR = 'TemplateRenderError',
+	DATABASE_ERROR = 'DatabaseError',
+}
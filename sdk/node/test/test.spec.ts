@@ -5,7 +5,7 @@ import { Meel, MeelSender, SentMeel } from '../src';
 const baseUrl = 'http://localhost:8080';
 
 test('send email', async () => {
-	const sender = new MeelSender({ baseUrl });
+	const sender = new MeelSender({ baseUrl, apiKey: process.env.MEEL_API_KEY });
 
 	const meel = new Meel({
 		subject: 'Hello world',